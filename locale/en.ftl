@@ -4,6 +4,9 @@ save-button = Save
 add-button = Add
 delete-button = Delete
 install-button = Install
+repair-button = Repair
+clean-button = Clean up
+revert-button = Revert
 enable-button = Enable
 
 # Login
@@ -26,6 +29,7 @@ server-name-label = Name
 server-host-label = Host
 server-launcher-port-label = Launcher Port
 server-game-port-label = Game Port
+server-public-key-label = Patch Signing Key (optional)
 server-game-folder-label = Server Game Path
 server-game-version-label = Version
 
@@ -51,6 +55,8 @@ fullscreen-resolution-label = Fullscreen Resolution
 list-remote-servers-label = List Remote Servers
 list-remote-messages-label = List Global Messages
 serverlist-url-label = Serverlist URL
+download-limit-label = Download Speed Limit
+download-limit-unit = KB/s (0 = unlimited)
 settings-error = Failed to write settings to 'mhf.ini'
 
 # Characters page
@@ -77,10 +83,15 @@ patcher-updates-label = Server Updates
 patcher-updates-confirmation =
     A new patch has been found that needs to be installed before you can play, would you like to install it?<br>
     <span class="warning">You can restore your files back to the original in the settings under Maintenance.</span>
+repair-label = Repair Game Files
+repair-confirmation = { $missing } missing and { $corrupt } damaged files were found, and { $deleted } files should be removed. Would you like to repair them now?
 patcher-checking = Checking updates...
 patcher-percentage = [{ $percentage }%]Downloading files...
 patcher-progress = { $current } out of { $total } files downloaded
+patcher-bytes = { $downloaded } / { $total } ({ $speed }/s)
+patcher-bytes-eta = { $downloaded } / { $total } ({ $speed }/s, { $eta } left)
 patcher-patching = Finishing...
+patcher-deleting = Removing { $count } obsolete files
 
 # Weapons
 greatsword-label = Greatsword
@@ -102,6 +113,7 @@ magnet-spike-label = Magnet Spike
 endpoint-name-empty = Server name must not be empty
 endpoint-host-empty = Server host must not be empty
 endpoint-unique = Server names must be unique
+endpoint-key-invalid = Patch signing key must be a base64 Ed25519 public key
 file-error = Failed to manage files
 path-folder-error = Path must be a directory
 path-exists-error = The specified game folder does not exist
@@ -111,6 +123,11 @@ remote-messages-error = Unable to fetch global messages
 launcher-network-error = Launcher failed to connect to launcher server
 patcher-network-error = Patcher failed to connect to patcher server
 patcher-file-error = Patcher failed to manage files in game folder
+patcher-hash-mismatch = Downloaded patch files are corrupted, please try again later
+patcher-list-error = The server sent an invalid patch list, please try again later
+patcher-signature-error = The patch list is not signed with this server's key, refusing to install it
+patcher-disk-space = Not enough free disk space in the game folder to install the update
+folder-in-use = The game folder is in use by another launcher or the running game, please try again once it's done
 internal-error = Launcher error, check logs
 
 # Remote
@@ -122,3 +139,32 @@ username-password-empty-error = Username and password must not be empty
 reset-patch-label = Maintenance
 reset-button-label = Reset patched files
 resetting-label = Resetting…
+verify-label = Game Files
+verify-button-label = Verify and repair
+verifying-label = Verifying…
+verify-clean = All game files are intact
+verify-login-error = Log in to a server to verify game files against it
+verify-no-server-error = This server doesn't provide game files to verify against
+inspect-label = Patched Files
+inspect-button-label = Inspect
+inspect-empty = No server has patched this game folder
+inspect-server = { $server }: { $modified } replaced, { $added } added and { $deleted } removed files, { $damaged } backups missing or changed
+inspect-orphans = { $count } backups don't belong to any server's patch. Clean them up to free space.
+inspect-cleaned = Removed { $count } orphaned backups
+snapshot-label = Vanilla Snapshot
+snapshot-create-button-label = Take snapshot
+snapshot-compare-button-label = Compare
+snapshot-busy-label = Working…
+snapshot-created = Snapshot taken of { $count } files
+snapshot-taken = Snapshot taken { $date }
+snapshot-status = { $modified } files changed, { $missing } missing and { $added } added since. Reverting puts them back the way they were, deletes the added files and forgets every server's patch.
+snapshot-unrecoverable = { $count } files can't be reverted because their copy is gone.
+snapshot-reverted = Game files reverted to the snapshot
+snapshot-missing-error = No snapshot has been taken of this game folder yet
+snapshot-patched-error = A server's patch is still applied. Reset patched files before taking a snapshot
+install-client-label = Game Client
+install-client-button-label = Install into empty folder…
+install-folder-not-empty = Choose an empty folder to install the game client into
+install-login-error = Log in to a server to install its game client
+install-no-server-error = This server doesn't provide a game client to install
+install-server-folder-error = This server uses its own Server Game Path. Clear it to install the game client into another folder
@@ -0,0 +1,218 @@
+//! Content-addressed store for the originals patches replace or delete.
+//!
+//! Originals are kept once per content as `ButterClient/backups/<sha256>`,
+//! however many servers patch them, and manifests refer to them by hash. The
+//! vanilla snapshot keeps its copies of the client here too.
+//! Older launchers kept them as `*.butterold` next to the live file; those
+//! are still found and restored, and take precedence over the store until
+//! `migrate_legacy` moves them in.
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use log::{info, warn};
+
+use crate::hash_index::hash_file;
+use crate::manifest::{self, Manifest};
+use crate::patcher::backup_name;
+use crate::snapshot::Snapshot;
+
+pub const BACKUP_DIR: &str = "ButterClient/backups";
+
+pub fn blob_path(root: &Path, hash: &str) -> PathBuf {
+    root.join(BACKUP_DIR).join(hash)
+}
+
+/// Hashes the contents of `file`. Blobs are named after the bytes they hold,
+/// so the hash index, which can be stale, is never trusted for it.
+fn hash_contents(file: &Path) -> io::Result<String> {
+    hash_file(file).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("unable to hash {}", file.display()))
+    })
+}
+
+/// Moves `file`, which `hash_contents` just hashed to `hash`, into the store.
+/// If the store already holds those contents the file is simply removed.
+fn insert(root: &Path, file: &Path, hash: &str) -> io::Result<()> {
+    let blob = blob_path(root, hash);
+    if blob.exists() {
+        return fs::remove_file(file);
+    }
+    fs::create_dir_all(blob.parent().unwrap())?;
+    fs::rename(file, blob)
+}
+
+/// Hashes `file` and moves it into the store, returning the hash.
+pub fn store(root: &Path, file: &Path) -> io::Result<String> {
+    let hash = hash_contents(file)?;
+    insert(root, file, &hash)?;
+    Ok(hash)
+}
+
+/// Hashes `file` and copies it into the store unless it already holds those
+/// contents, returning the hash.
+pub fn store_copy(root: &Path, file: &Path) -> io::Result<String> {
+    let hash = hash_contents(file)?;
+    let blob = blob_path(root, &hash);
+    if !blob.exists() {
+        fs::create_dir_all(blob.parent().unwrap())?;
+        let tmp = blob.with_extension("tmp");
+        fs::copy(file, &tmp)?;
+        fs::rename(tmp, blob)?;
+    }
+    Ok(hash)
+}
+
+/// The backup of the original at `root/rel`, if there is one.
+pub fn locate(root: &Path, rel: &str, original_hash: Option<&str>) -> Option<PathBuf> {
+    let bak = backup_name(&root.join(rel));
+    if bak.exists() {
+        return Some(bak);
+    }
+    original_hash
+        .map(|hash| blob_path(root, hash))
+        .filter(|blob| blob.exists())
+}
+
+/// Puts the original of `root/rel` back in place, replacing whatever is
+/// there. Store blobs are copied since other servers may share them; `gc`
+/// drops them once unused. Returns `false` if there is no backup.
+pub fn restore(root: &Path, rel: &str, original_hash: Option<&str>) -> io::Result<bool> {
+    let orig = root.join(rel);
+    let Some(bak) = locate(root, rel, original_hash) else {
+        return Ok(false);
+    };
+    if let Some(parent) = orig.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if bak == backup_name(&orig) {
+        let _ = fs::remove_file(&orig);
+        fs::rename(bak, orig)?;
+    } else {
+        fs::copy(bak, orig)?;
+    }
+    Ok(true)
+}
+
+/// Moves the `*.butterold` backups of older launchers that a manifest refers
+/// to into the store and records their hashes. Backups that no manifest
+/// refers to are left for `manifest::remove_orphaned_backups`. Blocks while
+/// hashing them.
+pub fn migrate_legacy(root: &Path) {
+    for server in manifest::servers(root) {
+        let mut manifest = Manifest::load(root, &server);
+        // A stashed server's originals are in place, not backed up.
+        if manifest.stashed {
+            continue;
+        }
+        let mut legacy = Vec::new();
+        for rel in manifest.modified_files.iter().chain(&manifest.deleted_files) {
+            let bak = backup_name(&root.join(rel));
+            if !bak.exists() {
+                continue;
+            }
+            let hash = match hash_contents(&bak) {
+                Ok(hash) => hash,
+                Err(e) => {
+                    warn!("failed to hash legacy backup of {}: {}", rel, e);
+                    continue;
+                }
+            };
+            match manifest.original_hash(rel) {
+                Some(recorded) if recorded != hash => {
+                    warn!("legacy backup of {} no longer matches the original, leaving it", rel);
+                }
+                _ => legacy.push((rel.clone(), bak, hash)),
+            }
+        }
+        if legacy.is_empty() {
+            continue;
+        }
+        // The manifest goes first: until a backup is moved, `locate` still
+        // finds it under its legacy name.
+        for (rel, _, hash) in &legacy {
+            manifest.entries.entry(rel.clone()).or_default().original_hash = Some(hash.clone());
+        }
+        if let Err(e) = manifest.save(root, &server) {
+            warn!("failed to save manifest of {}: {}", server, e);
+            continue;
+        }
+        for (rel, bak, hash) in legacy {
+            match insert(root, &bak, &hash) {
+                Ok(()) => info!("moved legacy backup of {} into the store", rel),
+                Err(e) => warn!("failed to move legacy backup of {}: {}", rel, e),
+            }
+        }
+    }
+}
+
+/// Blobs that neither a manifest nor the snapshot refers to, relative to the
+/// game folder.
+pub fn unused(root: &Path) -> Vec<String> {
+    let mut referenced: HashSet<String> = manifest::servers(root)
+        .into_iter()
+        .flat_map(|server| Manifest::load(root, &server).entries.into_values())
+        .filter_map(|entry| entry.original_hash)
+        .collect();
+    if let Some(snapshot) = Snapshot::load(root) {
+        referenced.extend(snapshot.files.into_values().map(|file| file.hash));
+    }
+    let mut unused: Vec<String> = fs::read_dir(root.join(BACKUP_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|blob| blob.file_name().into_string().ok())
+        .filter(|hash| !referenced.contains(hash))
+        .map(|hash| format!("{BACKUP_DIR}/{hash}"))
+        .collect();
+    unused.sort();
+    unused
+}
+
+/// Deletes every unused blob, returning the ones removed.
+pub fn gc(root: &Path) -> Vec<String> {
+    unused(root)
+        .into_iter()
+        .filter(|rel| match fs::remove_file(root.join(rel)) {
+            Ok(()) => {
+                info!("removed unused backup {}", rel);
+                true
+            }
+            Err(e) => {
+                warn!("failed to remove unused backup {}: {}", rel, e);
+                false
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_legacy() {
+        let root = std::env::temp_dir().join("butter-backup-store-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.bin"), "patched").unwrap();
+        fs::write(root.join("a.bin.butterold"), "original").unwrap();
+        fs::write(root.join("b.bin.butterold"), "orphaned").unwrap();
+        let manifest = Manifest {
+            modified_files: vec!["a.bin".into()],
+            ..Default::default()
+        };
+        manifest.save(&root, "test").unwrap();
+
+        migrate_legacy(&root);
+        assert!(!root.join("a.bin.butterold").exists());
+        assert!(root.join("b.bin.butterold").exists());
+        let hash = Manifest::load(&root, "test").original_hash("a.bin").unwrap().to_owned();
+        assert_eq!(fs::read_to_string(blob_path(&root, &hash)).unwrap(), "original");
+
+        crate::patcher::restore_server(&root, "test").unwrap();
+        assert_eq!(fs::read_to_string(root.join("a.bin")).unwrap(), "original");
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,149 @@
+//! Headless commands for support staff, run as `<launcher> <command> ...`
+//! instead of opening the launcher window.
+use std::{path::PathBuf, process::exit};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+
+use crate::folder_lock::FolderLock;
+use crate::manifest;
+use crate::patcher::{self, PatcherConfig};
+use crate::snapshot;
+
+#[derive(Parser, Debug)]
+#[command(about = Some("Headless launcher commands. Run without arguments to open the launcher."))]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Prints what patching the game folder would change, as JSON, without
+    /// writing anything.
+    Plan {
+        #[arg(long, help = "Game folder to compare against the patch list")]
+        game_folder: PathBuf,
+        #[arg(long, help = "Patch server URL, as sent by the server on login")]
+        patch_server: String,
+        #[arg(long = "mirror", help = "Patch server mirror, tried in order; can be repeated")]
+        mirrors: Vec<String>,
+        #[arg(long, help = "Base64 Ed25519 key the patch list must be signed with")]
+        public_key: Option<String>,
+    },
+    /// Prints, as JSON, what each server's patch changed in the game folder,
+    /// which `.butterold` backups no manifest refers to and which backup
+    /// store blobs nothing uses.
+    Inspect {
+        #[arg(long, help = "Game folder to inspect")]
+        game_folder: PathBuf,
+        #[arg(long, help = "Delete the orphaned backups after inspecting")]
+        clean: bool,
+    },
+    /// Takes a snapshot of the whole client, or compares or reverts the
+    /// client to it, printing the differences as JSON.
+    Snapshot {
+        #[arg(long, help = "Game folder to snapshot")]
+        game_folder: PathBuf,
+        #[arg(value_enum)]
+        action: SnapshotAction,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SnapshotAction {
+    Create,
+    Status,
+    Revert,
+}
+
+/// Runs a headless command if one was given. Returns `false` when the
+/// launcher should start as usual.
+pub fn run() -> bool {
+    // Leave flags, folders passed by shortcuts and the like to the launcher
+    // itself; only a known command name makes this a headless run.
+    let Some(arg) = std::env::args().nth(1) else {
+        return false;
+    };
+    if !Cli::command().get_subcommands().any(|c| c.get_name() == arg) {
+        return false;
+    }
+    attach_console();
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => e.exit(),
+    };
+    let client = reqwest::ClientBuilder::new().gzip(true).build().unwrap();
+
+    match cli.command {
+        Command::Plan {
+            game_folder,
+            patch_server,
+            mirrors,
+            public_key,
+        } => {
+            let result = tauri::async_runtime::block_on(patcher::plan(
+                &client,
+                &patcher::mirror_urls(&patch_server, &mirrors),
+                public_key.as_deref(),
+                &game_folder,
+                &PatcherConfig::default(),
+            ));
+            match result {
+                Ok(report) => println!("{}", serde_json::to_string_pretty(&report).unwrap()),
+                Err(e) => {
+                    eprintln!("error planning patch: {}", e);
+                    exit(2);
+                }
+            }
+        }
+        Command::Inspect { game_folder, clean } => {
+            let inspection = manifest::inspect(&game_folder);
+            println!("{}", serde_json::to_string_pretty(&inspection).unwrap());
+            if clean {
+                let _lock = match FolderLock::acquire(&game_folder, "cleanup") {
+                    Ok(lock) => lock,
+                    Err(e) => {
+                        eprintln!("error cleaning up backups: {}", e);
+                        exit(2);
+                    }
+                };
+                for rel in manifest::remove_orphaned_backups(&game_folder) {
+                    eprintln!("removed {}", rel);
+                }
+            }
+        }
+        Command::Snapshot {
+            game_folder,
+            action,
+        } => {
+            let result = match action {
+                SnapshotAction::Create => snapshot::create(&game_folder).map(|snapshot| {
+                    eprintln!("snapshot taken of {} files", snapshot.files.len());
+                    None
+                }),
+                SnapshotAction::Status => snapshot::compare(&game_folder).map(Some),
+                SnapshotAction::Revert => snapshot::revert(&game_folder).map(Some),
+            };
+            match result {
+                Ok(Some(divergence)) => {
+                    println!("{}", serde_json::to_string_pretty(&divergence).unwrap())
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("snapshot {:?} failed: {}", action, e);
+                    exit(2);
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Release builds use the windows subsystem, so output only shows up in the
+/// terminal that started us once we attach to its console.
+fn attach_console() {
+    #[cfg(target_os = "windows")]
+    unsafe {
+        use windows::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+        let _ = AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
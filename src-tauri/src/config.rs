@@ -20,6 +20,8 @@ pub fn get_default_endpoints() -> Vec<Endpoint> {
             game_folder: None,
             version: mhf_iel::MhfVersion::ZZ,
             is_remote: true,
+            patch_mirrors: Vec::new(),
+            patch_public_key: None,
         },
         Endpoint {
             name: "Offline-Mode".into(),
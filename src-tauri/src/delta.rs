@@ -0,0 +1,62 @@
+//! Binary delta patches in zstd's `--patch-from` format.
+//!
+//! Server operators create them with
+//! `zstd --patch-from=<old file> <new file> -o <delta>`.
+use std::{fs, io::{self, BufReader}, path::Path};
+use sha2::{Digest, Sha256};
+
+/// Largest window a delta may reference (1 GiB), enough for any client file
+/// while staying addressable on 32-bit builds.
+pub const WINDOW_LOG_MAX: u32 = 30;
+
+/// Rebuilds `target` from the local `base` file and a downloaded `delta`.
+/// Fails if `base` no longer hashes to the `base_hash` the delta was made
+/// from, which the hash index can't rule out.
+pub fn apply(base: &Path, base_hash: &str, delta: &Path, target: &Path) -> io::Result<()> {
+    let base = fs::read(base)?;
+    if format!("{:x}", Sha256::digest(&base)) != base_hash {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "delta base changed"));
+    }
+    let delta = BufReader::new(fs::File::open(delta)?);
+    let mut decoder = zstd::Decoder::with_ref_prefix(delta, &base)?;
+    decoder.window_log_max(WINDOW_LOG_MAX)?;
+    let mut file = fs::File::create(target)?;
+    io::copy(&mut decoder, &mut file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash_index::hash_file;
+
+    #[test]
+    fn test_apply() {
+        let dir = std::env::temp_dir().join("butter-delta-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let base: Vec<u8> = (0..100_000u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+        let mut new = base.clone();
+        new[1234..1300].fill(7);
+        new.extend_from_slice(b"appended");
+        fs::write(dir.join("base"), &base).unwrap();
+        fs::write(dir.join("new"), &new).unwrap();
+
+        // What `zstd --patch-from=base new -o delta` writes.
+        let mut encoder = zstd::Encoder::with_ref_prefix(Vec::new(), 19, &base).unwrap();
+        encoder.include_checksum(true).unwrap();
+        io::copy(&mut new.as_slice(), &mut encoder).unwrap();
+        let delta = encoder.finish().unwrap();
+        assert!(delta.len() < new.len() / 10);
+        fs::write(dir.join("delta"), delta).unwrap();
+
+        let base_hash = hash_file(&dir.join("base")).unwrap();
+        apply(&dir.join("base"), &base_hash, &dir.join("delta"), &dir.join("target")).unwrap();
+        assert_eq!(hash_file(&dir.join("target")), hash_file(&dir.join("new")));
+
+        let wrong_hash = hash_file(&dir.join("new")).unwrap();
+        let result = apply(&dir.join("base"), &wrong_hash, &dir.join("delta"), &dir.join("target"));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -2,6 +2,8 @@ use std::{collections::HashMap, path::PathBuf, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
+use crate::signature;
+
 #[derive(Default, Clone, Serialize, Deserialize)]
 pub struct EndpointConfig {
     pub game_folder: Option<PathBuf>,
@@ -18,6 +20,12 @@ pub struct Endpoint {
     pub version: mhf_iel::MhfVersion,
     #[serde(default)]
     pub is_remote: bool,
+    /// Extra patch server mirrors, tried after the ones sent on login.
+    #[serde(default)]
+    pub patch_mirrors: Vec<String>,
+    /// Base64 Ed25519 key that must have signed the server's patch lists.
+    #[serde(default)]
+    pub patch_public_key: Option<String>,
 }
 
 impl PartialEq for Endpoint {
@@ -61,6 +69,11 @@ impl EndpointVecExt for Vec<Endpoint> {
             } else if self.iter().filter(|e| e.name == endpoint.name).count() > 1 {
                 return Err("endpoint-unique");
             }
+            if let Some(key) = endpoint.patch_public_key.as_deref() {
+                if signature::parse_key(key).is_none() {
+                    return Err("endpoint-key-invalid");
+                }
+            }
             if let Some(game_folder) = endpoint.game_folder.as_ref() {
                 if !game_folder.exists() {
                     return Err("path-exists-error");
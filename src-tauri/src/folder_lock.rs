@@ -0,0 +1,180 @@
+//! Advisory lock that keeps launchers from patching, restoring or launching
+//! the same game folder at once.
+//!
+//! The holder's PID and operation are kept in `ButterClient/launcher.lock`.
+//! A lock whose process is gone is stale and taken over.
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+pub const LOCK_FILE: &str = "ButterClient/launcher.lock";
+pub const FOLDER_IN_USE_ERROR: &str = "folder-in-use";
+/// How long a lock file may stay empty while its creator writes it.
+const WRITE_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Holder {
+    pid: u32,
+    operation: String,
+}
+
+/// Held lock on a game folder, released when dropped.
+pub struct FolderLock {
+    path: PathBuf,
+}
+
+impl FolderLock {
+    /// Locks `root` for `operation`, e.g. "patch". Fails with
+    /// `FOLDER_IN_USE_ERROR` if another live process holds the lock.
+    pub fn acquire(root: &Path, operation: &str) -> Result<Self, &'static str> {
+        let path = root.join(LOCK_FILE);
+        let holder = Holder {
+            pid: std::process::id(),
+            operation: operation.to_owned(),
+        };
+        // A second attempt follows clearing a stale lock.
+        for _ in 0..2 {
+            match create(&path, &holder) {
+                Ok(()) => return Ok(Self { path }),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    warn!("failed to create {:?}: {}", path, e);
+                    return Err(crate::patcher::FILE_ERROR);
+                }
+            }
+            check(root)?;
+        }
+        Err(FOLDER_IN_USE_ERROR)
+    }
+
+    /// Leaves the lock to `pid`, a process that outlives this one such as a
+    /// detached game. It turns stale once that process exits.
+    pub fn hand_over(self, pid: u32) {
+        let mut holder = read(&self.path).unwrap_or(Holder {
+            pid,
+            operation: String::new(),
+        });
+        holder.pid = pid;
+        if let Err(e) = fs::write(&self.path, serde_json::to_vec(&holder).unwrap()) {
+            warn!("failed to hand over game folder lock: {}", e);
+        }
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for FolderLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn create(path: &Path, holder: &Holder) -> io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    file.write_all(&serde_json::to_vec(holder)?)
+}
+
+fn read(path: &Path) -> Option<Holder> {
+    fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+}
+
+/// Fails with `FOLDER_IN_USE_ERROR` if a live process holds the lock on
+/// `root`, and clears the lock if its process is gone.
+pub fn check(root: &Path) -> Result<(), &'static str> {
+    let path = root.join(LOCK_FILE);
+    match read(&path) {
+        Some(holder) if is_running(holder.pid) => {
+            warn!(
+                "game folder is locked by pid {} for {}",
+                holder.pid, holder.operation
+            );
+            return Err(FOLDER_IN_USE_ERROR);
+        }
+        Some(holder) => info!("clearing stale lock of pid {}", holder.pid),
+        None => {
+            let Ok(modified) = fs::metadata(&path).and_then(|m| m.modified()) else {
+                return Ok(());
+            };
+            // Probably still being written by whoever just created it.
+            if SystemTime::now()
+                .duration_since(modified)
+                .map_or(true, |age| age < WRITE_GRACE)
+            {
+                return Err(FOLDER_IN_USE_ERROR);
+            }
+            info!("clearing unreadable lock");
+        }
+    }
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            warn!("failed to clear stale lock: {}", e);
+            Err(FOLDER_IN_USE_ERROR)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(target_os = "windows")]
+fn is_running(pid: u32) -> bool {
+    use windows::Win32::Foundation::{CloseHandle, ERROR_INVALID_PARAMETER, FALSE};
+    use windows::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    // Exit code of processes that haven't exited yet.
+    const STILL_ACTIVE: u32 = 259;
+    unsafe {
+        let process = match OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, FALSE, pid) {
+            Ok(process) => process,
+            // Anything else, like access denied, means the process exists.
+            Err(e) => return e.code() != ERROR_INVALID_PARAMETER.to_hresult(),
+        };
+        let mut code = 0;
+        let running = GetExitCodeProcess(process, &mut code).is_err() || code == STILL_ACTIVE;
+        let _ = CloseHandle(process);
+        running
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn is_running(_pid: u32) -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock() {
+        let root = std::env::temp_dir().join("butter-lock-test");
+        let _ = fs::remove_dir_all(&root);
+
+        let lock = FolderLock::acquire(&root, "patch").unwrap();
+        assert_eq!(
+            FolderLock::acquire(&root, "restore").err(),
+            Some(FOLDER_IN_USE_ERROR)
+        );
+        drop(lock);
+        assert!(!root.join(LOCK_FILE).exists());
+
+        // No process has this PID, so the lock is stale.
+        FolderLock::acquire(&root, "launch").unwrap().hand_over(u32::MAX);
+        FolderLock::acquire(&root, "patch").unwrap();
+        let _ = fs::remove_dir_all(&root);
+    }
+}
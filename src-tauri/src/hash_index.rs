@@ -0,0 +1,209 @@
+//! Persistent cache of client file hashes, so unchanged files aren't rehashed
+//! on every patch run.
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::UNIX_EPOCH,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+pub const HASH_INDEX_FILE: &str = "launcher_config/hash_index.json";
+
+pub fn hash_file(path: &Path) -> Option<String> {
+    let mut file = fs::File::open(path).ok()?;
+    let mut hasher = sha2::Sha256::new();
+    io::copy(&mut file, &mut hasher).ok()?;
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FileStamp {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            size: meta.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexEntry {
+    #[serde(flatten)]
+    stamp: FileStamp,
+    hash: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct HashIndex {
+    files: HashMap<String, IndexEntry>,
+}
+
+impl HashIndex {
+    pub fn path(root: &Path) -> PathBuf {
+        root.join(HASH_INDEX_FILE)
+    }
+
+    pub fn load(root: &Path) -> Self {
+        fs::read_to_string(Self::path(root))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, root: &Path) -> io::Result<()> {
+        let final_ = Self::path(root);
+        fs::create_dir_all(final_.parent().unwrap())?;
+        let tmp = final_.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(tmp, final_)
+    }
+
+    /// Returns the cached hash of `rel` if the file is unchanged since it was
+    /// hashed.
+    fn cached(&self, rel: &str, stamp: &FileStamp) -> Option<String> {
+        self.files
+            .get(rel)
+            .filter(|entry| &entry.stamp == stamp)
+            .map(|entry| entry.hash.clone())
+    }
+
+    /// Records a hash already known to match the file at `root/rel`, e.g.
+    /// one verified before being moved into place.
+    pub fn record(&mut self, root: &Path, rel: &str, hash: &str) {
+        if let Some(stamp) = FileStamp::of(&root.join(rel)) {
+            self.files.insert(
+                rel.to_owned(),
+                IndexEntry {
+                    stamp,
+                    hash: hash.to_owned(),
+                },
+            );
+        }
+    }
+
+    /// Hashes every file in `paths` relative to `root`, reusing cached hashes
+    /// for files whose size and mtime didn't change. Missing or unreadable
+    /// files yield `None`. Blocks, so call it off the async runtime.
+    pub fn hash_files(&mut self, root: &Path, paths: &[String]) -> Vec<Option<String>> {
+        let mut results = vec![None; paths.len()];
+        let mut pending = Vec::new();
+        for (i, rel) in paths.iter().enumerate() {
+            let Some(stamp) = FileStamp::of(&root.join(rel)) else {
+                self.files.remove(rel);
+                continue;
+            };
+            match self.cached(rel, &stamp) {
+                Some(hash) => results[i] = Some(hash),
+                None => pending.push((i, stamp)),
+            }
+        }
+        info!(
+            "hash index: {} cached, {} to hash",
+            paths.len() - pending.len(),
+            pending.len()
+        );
+
+        let next = AtomicUsize::new(0);
+        let hashed = Mutex::new(Vec::with_capacity(pending.len()));
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        thread::scope(|scope| {
+            for _ in 0..workers.min(pending.len()) {
+                scope.spawn(|| {
+                    while let Some((i, _)) = pending.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let hash = hash_file(&root.join(&paths[*i]));
+                        hashed.lock().unwrap().push((*i, hash));
+                    }
+                });
+            }
+        });
+
+        let stamps: HashMap<_, _> = pending.into_iter().collect();
+        for (i, hash) in hashed.into_inner().unwrap() {
+            if let Some(hash) = &hash {
+                self.files.insert(
+                    paths[i].clone(),
+                    IndexEntry {
+                        stamp: stamps[&i].clone(),
+                        hash: hash.clone(),
+                    },
+                );
+            }
+            results[i] = hash;
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    const FAKE: &str = "cached";
+
+    #[test]
+    fn test_hash_files() {
+        let root = std::env::temp_dir().join("butter-hash-index-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.bin"), "original").unwrap();
+        fs::write(root.join("b.bin"), "original").unwrap();
+        let paths = ["a.bin".to_owned(), "b.bin".to_owned()];
+        let original = hash_file(&root.join("a.bin"));
+
+        let mut index = HashIndex::default();
+        assert_eq!(index.hash_files(&root, &paths), [original.clone(), original.clone()]);
+        // Only a cache hit can return what the index claims.
+        for entry in index.files.values_mut() {
+            entry.hash = FAKE.to_owned();
+        }
+        assert_eq!(index.hash_files(&root, &paths), [Some(FAKE.into()), Some(FAKE.into())]);
+
+        // A different size, and the same size at a different mtime.
+        fs::write(root.join("a.bin"), "patched!!").unwrap();
+        let file = fs::File::options().write(true).open(root.join("b.bin")).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert_eq!(
+            index.hash_files(&root, &paths),
+            [hash_file(&root.join("a.bin")), original]
+        );
+
+        fs::remove_file(root.join("a.bin")).unwrap();
+        assert_eq!(index.hash_files(&root, &paths)[0], None);
+        assert!(!index.files.contains_key("a.bin"));
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let root = std::env::temp_dir().join("butter-hash-index-save-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.bin"), "original").unwrap();
+
+        let mut index = HashIndex::default();
+        index.record(&root, "a.bin", FAKE);
+        index.save(&root).unwrap();
+        let mut index = HashIndex::load(&root);
+        assert_eq!(index.hash_files(&root, &["a.bin".to_owned()]), [Some(FAKE.into())]);
+        assert!(HashIndex::load(&root.join("missing")).files.is_empty());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
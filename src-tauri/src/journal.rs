@@ -0,0 +1,393 @@
+//! Write-ahead journal that makes applying a patch all-or-nothing.
+//!
+//! Before the first file is touched the journal is written with every path
+//! the patch will replace or delete. Originals are parked as `*.butterpending` while the
+//! patch is applied, so a failure can always be undone. Once every file is in
+//! place the journal switches to `Committing`, the parked originals move into
+//! the backup store and the manifest is saved. A journal found on
+//! startup is rolled back or finished depending on its phase.
+//!
+//! Repairs from the server's reference list go through the journal too, but
+//! what they replace is damaged rather than original: it is discarded on
+//! commit, and files the server's patch doesn't track stay out of the
+//! manifest so resetting the patch leaves them repaired.
+use std::{fs, io, path::{Path, PathBuf}};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::backup_store;
+use crate::hash_index::HashIndex;
+use crate::manifest::Manifest;
+
+pub const JOURNAL_FILE: &str = "ButterClient/patch_journal.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Applying,
+    Committing,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub path: String,
+    /// The game folder had a file at `path` before the patch.
+    pub existed: bool,
+    /// The server's manifest already tracks `path` from an earlier patch, so
+    /// the file on disk is not an original worth backing up.
+    pub tracked: bool,
+    /// The patch list deletes `path` instead of replacing it.
+    #[serde(default)]
+    pub delete: bool,
+    /// Hash of the file the patch puts in place, none for deletions.
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// Hash of the untracked file that is about to be backed up, as the hash
+    /// index had it. Committing corrects it from the bytes it stores.
+    #[serde(default)]
+    pub original_hash: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Journal {
+    pub server: String,
+    /// The server's `ButterVersion.txt` being applied.
+    #[serde(default)]
+    pub patch_version: String,
+    pub phase: Phase,
+    /// Applies a repair rather than a patch.
+    #[serde(default)]
+    pub repair: bool,
+    pub entries: Vec<JournalEntry>,
+}
+
+/// foo/bar.txt → foo/bar.txt.butterpending
+fn pending_name(p: &Path) -> PathBuf {
+    let mut f = p.file_name().unwrap().to_os_string();
+    f.push(".butterpending");
+    p.with_file_name(f)
+}
+
+impl Journal {
+    /// Journals replacing each `(path, hash)` of `files` and deleting each
+    /// path of `deleted`, as a patch or as a `repair`. Hashes the originals
+    /// a patch will back up, which blocks unless the hash index has them
+    /// cached.
+    pub fn new(
+        root: &Path,
+        server: &str,
+        patch_version: &str,
+        repair: bool,
+        files: impl IntoIterator<Item = (String, String)>,
+        deleted: impl IntoIterator<Item = String>,
+    ) -> Self {
+        let manifest = Manifest::load(root, server);
+        let mut entries: Vec<JournalEntry> = files
+            .into_iter()
+            .map(|(path, hash)| (path, Some(hash)))
+            .chain(deleted.into_iter().map(|path| (path, None)))
+            .map(|(path, hash)| JournalEntry {
+                existed: root.join(&path).exists(),
+                tracked: manifest.tracks(&path),
+                delete: hash.is_none(),
+                hash,
+                original_hash: None,
+                path,
+            })
+            .collect();
+
+        if !repair {
+            let originals: Vec<String> = entries
+                .iter()
+                .filter(|e| e.existed && !e.tracked)
+                .map(|e| e.path.clone())
+                .collect();
+            let mut hashes = HashIndex::load(root).hash_files(root, &originals).into_iter();
+            for entry in entries.iter_mut().filter(|e| e.existed && !e.tracked) {
+                entry.original_hash = hashes.next().flatten();
+            }
+        }
+        Self {
+            server: server.to_owned(),
+            patch_version: patch_version.to_owned(),
+            phase: Phase::Applying,
+            repair,
+            entries,
+        }
+    }
+
+    pub fn path(root: &Path) -> PathBuf {
+        root.join(JOURNAL_FILE)
+    }
+
+    pub fn load(root: &Path) -> Option<Self> {
+        fs::read_to_string(Self::path(root))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+    }
+
+    pub fn save(&self, root: &Path) -> io::Result<()> {
+        let final_ = Self::path(root);
+        fs::create_dir_all(final_.parent().unwrap())?;
+        let tmp = final_.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(tmp, final_)
+    }
+
+    pub fn delete(root: &Path) {
+        let _ = fs::remove_file(Self::path(root));
+    }
+
+    /// Moves every journaled file from `source_folder` into `root`, parking
+    /// the files it replaces or deletes. Call `rollback` if this fails.
+    pub fn apply(&self, root: &Path, source_folder: &Path) -> io::Result<()> {
+        self.save(root)?;
+        for entry in &self.entries {
+            let source = source_folder.join(&entry.path);
+            let target = root.join(&entry.path);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if entry.existed {
+                fs::rename(&target, pending_name(&target))?;
+            }
+            if entry.delete {
+                continue;
+            }
+            fs::rename(&source, &target)?;
+        }
+        Ok(())
+    }
+
+    /// Puts every parked original back and forgets the journal.
+    pub fn rollback(&self, root: &Path) -> io::Result<()> {
+        for entry in self.entries.iter().rev() {
+            let target = root.join(&entry.path);
+            let pending = pending_name(&target);
+            if entry.existed {
+                if pending.exists() {
+                    let _ = fs::remove_file(&target);
+                    fs::rename(&pending, &target)?;
+                }
+            } else if target.exists() {
+                fs::remove_file(&target)?;
+            }
+        }
+        Self::delete(root);
+        Ok(())
+    }
+
+    /// Turns parked originals into backups and records them in the manifest,
+    /// or discards them for a repair. Safe to call again if interrupted.
+    pub fn commit(&mut self, root: &Path) -> io::Result<()> {
+        self.phase = Phase::Committing;
+        self.save(root)?;
+
+        let mut manifest = Manifest::load(root, &self.server);
+        for i in 0..self.entries.len() {
+            let entry = &self.entries[i];
+            let target = root.join(&entry.path);
+            let pending = pending_name(&target);
+            if pending.exists() {
+                if entry.tracked || self.repair {
+                    fs::remove_file(&pending)?;
+                } else {
+                    let hash = backup_store::store(root, &pending)?;
+                    // The hash index was stale or hashing failed when the
+                    // journal was written. Keep the journal in step in case
+                    // this commit is interrupted.
+                    if entry.original_hash.as_deref() != Some(hash.as_str()) {
+                        warn!("original of {} hashed differently than journaled", entry.path);
+                        self.entries[i].original_hash = Some(hash);
+                        self.save(root)?;
+                    }
+                }
+            }
+            let entry = &self.entries[i];
+            if entry.tracked {
+                if entry.delete {
+                    // Something this server added is simply gone again.
+                    manifest.added_files.retain(|p| p != &entry.path);
+                } else if manifest.deleted_files.contains(&entry.path) {
+                    // Brought back, but the backup still holds the original.
+                    manifest.deleted_files.retain(|p| p != &entry.path);
+                    manifest.modified_files.push(entry.path.clone());
+                }
+            } else if self.repair {
+                // Not part of the patch, so not the patch's to undo.
+                continue;
+            } else {
+                let list = if entry.delete {
+                    &mut manifest.deleted_files
+                } else if entry.existed {
+                    &mut manifest.modified_files
+                } else {
+                    &mut manifest.added_files
+                };
+                if !list.contains(&entry.path) {
+                    list.push(entry.path.clone());
+                }
+            }
+            let size = fs::metadata(&target).map(|m| m.len()).unwrap_or(0);
+            manifest.record(
+                &entry.path,
+                entry.original_hash.clone(),
+                entry.hash.clone(),
+                size,
+                &self.patch_version,
+            );
+        }
+        manifest.save(root, &self.server)?;
+        Self::delete(root);
+        Ok(())
+    }
+}
+
+/// Finishes or undoes a patch that was interrupted while being applied.
+pub fn recover(root: &Path) {
+    let Some(mut journal) = Journal::load(root) else {
+        return;
+    };
+    let result = match journal.phase {
+        Phase::Applying => {
+            info!("rolling back interrupted patch for {}", journal.server);
+            journal.rollback(root)
+        }
+        Phase::Committing => {
+            info!("finishing interrupted patch for {}", journal.server);
+            journal.commit(root)
+        }
+    };
+    if let Err(e) = result {
+        warn!("failed to recover interrupted patch: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_apply_rolls_back() {
+        let root = std::env::temp_dir().join("butter-journal-test");
+        let _ = fs::remove_dir_all(&root);
+        let tmp = root.join("tmp");
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(root.join("a.bin"), "original").unwrap();
+        fs::write(tmp.join("a.bin"), "patched").unwrap();
+        fs::write(tmp.join("b.bin"), "added").unwrap();
+
+        let files = ["a.bin", "b.bin", "missing.bin"].map(|p| (p.to_owned(), String::new()));
+        let journal = Journal::new(&root, "test", "1", false, files, []);
+        assert!(journal.apply(&root, &tmp).is_err());
+        assert_eq!(Journal::load(&root).unwrap().phase, Phase::Applying);
+
+        recover(&root);
+        assert_eq!(fs::read_to_string(root.join("a.bin")).unwrap(), "original");
+        assert!(!root.join("b.bin").exists());
+        assert!(Journal::load(&root).is_none());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_deleted_file_is_restored() {
+        let root = std::env::temp_dir().join("butter-journal-delete-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("old.dll"), "original").unwrap();
+
+        let mut journal = Journal::new(&root, "test", "1", false, [], ["old.dll".to_owned()]);
+        journal.apply(&root, &root.join("tmp")).unwrap();
+        journal.commit(&root).unwrap();
+        assert!(!root.join("old.dll").exists());
+        assert_eq!(Manifest::load(&root, "test").deleted_files, ["old.dll"]);
+
+        crate::patcher::restore_server(&root, "test").unwrap();
+        assert_eq!(fs::read_to_string(root.join("old.dll")).unwrap(), "original");
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_restore_checks_backup_hash() {
+        let root = std::env::temp_dir().join("butter-journal-hash-test");
+        let _ = fs::remove_dir_all(&root);
+        let tmp = root.join("tmp");
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(root.join("a.bin"), "original").unwrap();
+        fs::write(tmp.join("a.bin"), "patched").unwrap();
+        let original = crate::hash_index::hash_file(&root.join("a.bin"));
+        let patched = crate::hash_index::hash_file(&tmp.join("a.bin")).unwrap();
+
+        let mut journal = Journal::new(&root, "test", "1", false, [("a.bin".to_owned(), patched.clone())], []);
+        journal.apply(&root, &tmp).unwrap();
+        journal.commit(&root).unwrap();
+        let entry = Manifest::load(&root, "test").entries["a.bin"].clone();
+        assert_eq!(entry.original_hash, original);
+        assert_eq!(entry.patched_hash, Some(patched));
+        assert_eq!(entry.patch_version, "1");
+
+        let backup = crate::backup_store::blob_path(&root, original.as_deref().unwrap());
+        assert!(!root.join("a.bin.butterold").exists());
+        fs::write(backup, "tampered").unwrap();
+        assert!(crate::patcher::restore_server(&root, "test").is_err());
+        assert_eq!(fs::read_to_string(root.join("a.bin")).unwrap(), "patched");
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_repair_survives_reset() {
+        let root = std::env::temp_dir().join("butter-journal-repair-test");
+        let _ = fs::remove_dir_all(&root);
+        let tmp = root.join("tmp");
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(root.join("a.bin"), "original").unwrap();
+        fs::write(tmp.join("a.bin"), "patched").unwrap();
+        let mut journal = Journal::new(&root, "test", "1", false, [("a.bin".to_owned(), String::new())], []);
+        journal.apply(&root, &tmp).unwrap();
+        journal.commit(&root).unwrap();
+
+        // A damaged vanilla file and a damaged patched file get repaired.
+        fs::write(root.join("mhfo-hd.dll"), "CORRUPT").unwrap();
+        fs::write(tmp.join("mhfo-hd.dll"), "vanilla").unwrap();
+        fs::write(root.join("a.bin"), "CORRUPT").unwrap();
+        fs::write(tmp.join("a.bin"), "patched").unwrap();
+        let files = ["mhfo-hd.dll", "a.bin"].map(|p| (p.to_owned(), String::new()));
+        let mut journal = Journal::new(&root, "test", "1", true, files, []);
+        journal.apply(&root, &tmp).unwrap();
+        journal.commit(&root).unwrap();
+        let manifest = Manifest::load(&root, "test");
+        assert_eq!(manifest.modified_files, ["a.bin"]);
+        assert!(!manifest.entries.contains_key("mhfo-hd.dll"));
+
+        crate::patcher::restore_server(&root, "test").unwrap();
+        assert_eq!(fs::read_to_string(root.join("mhfo-hd.dll")).unwrap(), "vanilla");
+        assert_eq!(fs::read_to_string(root.join("a.bin")).unwrap(), "original");
+        assert!(!root.join("mhfo-hd.dll.butterold").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_commit_ignores_stale_original_hash() {
+        let root = std::env::temp_dir().join("butter-journal-stale-test");
+        let _ = fs::remove_dir_all(&root);
+        let tmp = root.join("tmp");
+        fs::create_dir_all(&tmp).unwrap();
+        fs::write(root.join("a.bin"), "original").unwrap();
+        fs::write(tmp.join("a.bin"), "patched").unwrap();
+        // The hash index claims the original is contents the store holds.
+        fs::write(root.join("other.bin"), "other").unwrap();
+        let stale = crate::backup_store::store(&root, &root.join("other.bin")).unwrap();
+
+        let mut journal = Journal::new(&root, "test", "1", false, [("a.bin".to_owned(), String::new())], []);
+        journal.entries[0].original_hash = Some(stale.clone());
+        journal.apply(&root, &tmp).unwrap();
+        journal.commit(&root).unwrap();
+        let original = Manifest::load(&root, "test").original_hash("a.bin").unwrap().to_owned();
+        assert_ne!(original, stale);
+        let blob = crate::backup_store::blob_path(&root, &original);
+        assert_eq!(fs::read_to_string(blob).unwrap(), "original");
+        let blob = crate::backup_store::blob_path(&root, &stale);
+        assert_eq!(fs::read_to_string(blob).unwrap(), "other");
+        let _ = fs::remove_dir_all(&root);
+    }
+}
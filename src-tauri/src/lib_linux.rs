@@ -58,7 +58,7 @@ fn install_japanese_fonts(game_folder: &std::path::Path, wineprefix: &str) {
     info!("Japanese fonts installation complete");
 }
 
-pub fn run_linux(cfg: MhfConfigLinux) -> std::io::Result<()> {
+pub fn run_linux(cfg: MhfConfigLinux) -> std::io::Result<u32> {
     info!("=== Monster Hunter Frontier - Linux Launcher ===");
     log_to_file("=== MHFZ Font Debug Log ===");
 
@@ -269,7 +269,7 @@ pub fn run_linux(cfg: MhfConfigLinux) -> std::io::Result<()> {
             log_to_file(&format!("✅ Wine process spawned (PID: {})", child.id()));
             info!("✅ Game launched successfully (PID: {})", child.id());
             info!("🎮 Game is running");
-            Ok(())
+            Ok(child.id())
         }
         Err(e) => {
             log_to_file(&format!("❌ Failed to launch Wine: {}", e));
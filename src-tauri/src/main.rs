@@ -5,15 +5,27 @@ all(not(debug_assertions), target_os = "windows"),
 // Disabilitata su stable / Linux
 // #![feature(iterator_try_collect)]
 
+mod backup_store;
+mod cli;
 mod config;
+mod delta;
 mod endpoint;
+mod folder_lock;
+mod hash_index;
 mod ini_parser;
+mod journal;
+mod patch_list;
 mod patcher;
+mod retry;
 mod server;
+mod server_state;
 mod settings;
+mod signature;
+mod snapshot;
 mod store;
 mod user;
 mod manifest;
+mod overlay;
 
 #[cfg(target_os = "linux")]
 mod lib_linux;
@@ -26,11 +38,13 @@ use std::{
     time::SystemTime,
 };
 
+use folder_lock::FolderLock;
 use log::{error, info, warn};
 use mhf_iel::MhfConfig;
 use serde::Serialize;
 use serde_json::Value;
 use server::{AuthResponse, JsonRequest, LauncherResponse, MessageData, PatcherResponse};
+use server_state::ServerState;
 use settings::Settings;
 use store::StoreHelper;
 use tauri::{async_runtime::Mutex, PhysicalSize};
@@ -51,6 +65,8 @@ enum ExitSignal {
 #[derive()]
 struct TauriState {
     client: reqwest::Client,
+    /// Shared with running downloads, so a new limit applies right away.
+    rate_limiter: Arc<patcher::RateLimiter>,
     state_sync: Arc<Mutex<TauriStateSync>>,
 }
 
@@ -70,6 +86,7 @@ struct TauriStateSync {
     last_char_id: Option<u32>,
     serverlist_url: String,
     messagelist_url: String,
+    patcher_config: patcher::PatcherConfig,
 
     exit_reason: Option<ExitSignal>,
 
@@ -118,6 +135,17 @@ impl TauriStateSync {
         self.auth_resp.as_ref().ok_or("internal-error")
     }
 
+    /// The patch server and its mirrors, from login and the server list.
+    fn patcher_urls(&self, auth_resp: &AuthResponse) -> Vec<String> {
+        patcher::mirror_urls(
+            &auth_resp.patch_server,
+            auth_resp
+            .patch_mirrors
+            .iter()
+            .chain(&self.current_endpoint.patch_mirrors),
+        )
+    }
+
     fn effective_folder(&self) -> PathBuf {
         self.current_endpoint
         .game_folder
@@ -193,6 +221,7 @@ struct InitialDataPayload {
     serverlist_url: String,
     messagelist_url: String,
     settings: Settings,
+    patcher_config: patcher::PatcherConfig,
 }
 
 #[tauri::command]
@@ -215,6 +244,7 @@ async fn initial_data(state: tauri::State<'_, TauriState>) -> Result<InitialData
        serverlist_url: state_sync.serverlist_url.clone(),
        messagelist_url: state_sync.messagelist_url.clone(),
        settings: settings::get_settings(&state_sync.effective_folder()),
+       patcher_config: state_sync.patcher_config.clone(),
     })
 }
 
@@ -249,6 +279,22 @@ async fn set_setting(
     settings::set_setting(&state_sync.effective_folder(), &setting, value)
 }
 
+#[tauri::command]
+async fn set_patcher_config(
+    state: tauri::State<'_, TauriState>,
+    patcher_config: patcher::PatcherConfig,
+) -> Result<(), String> {
+    let mut state_sync = state.state_sync.lock().await;
+    state
+    .rate_limiter
+    .set_limit(patcher_config.max_bytes_per_second);
+    state_sync.patcher_config = patcher_config.clone();
+    state_sync
+    .store
+    .with(|s| s.set("patcher_config", patcher_config));
+    Ok(())
+}
+
 #[tauri::command]
 async fn set_endpoints(
     state: tauri::State<'_, TauriState>,
@@ -356,6 +402,7 @@ async fn set_current_endpoint(
         server::launcher_request(
             &state.client,
             state_sync.cancel_launcher.clone(),
+            state_sync.patcher_config.retry_policy(),
                                  &state_sync.current_endpoint,
         )
     };
@@ -423,6 +470,7 @@ async fn set_serverlist_url(
             server::simple_request(
                 &state.client,
                 state_sync.cancel_serverlist.clone(),
+                state_sync.patcher_config.retry_policy(),
                                    &serverlist_url,
             )
         };
@@ -454,6 +502,7 @@ async fn set_messagelist_url(
             server::simple_request(
                 &state.client,
                 state_sync.cancel_messagelist.clone(),
+                state_sync.patcher_config.retry_policy(),
                                    &messagelist_url,
             )
         };
@@ -505,28 +554,8 @@ async fn auth(
     }
     info!("✅ [AUTH] Directory created: {:?}", launcher_config_dir);
 
-    // ── 2) read local client version ──────────────────────────────────
-    let version_file = game_folder.join("ButterVersion.txt");
-    info!("🔵 [AUTH] Reading version from: {:?}", version_file);
-    let local_version = std::fs::read_to_string(&version_file)
-    .unwrap_or_else(|e| {
-        warn!("⚠️ [AUTH] ButterVersion.txt not found: {}", e);
-        String::new()
-    })
-    .trim()
-    .to_string();
-    info!("🔵 [AUTH] Local version: '{}'", local_version);
-
-    // ── identify active server (whose files sit on disk) ──────────────
-    let active_server_file = launcher_config_dir.join("active_server");
-    info!("🔵 [AUTH] Reading active server from: {:?}", active_server_file);
-    let active_server = std::fs::read_to_string(&active_server_file)
-    .unwrap_or_else(|e| {
-        warn!("⚠️ [AUTH] active_server file not found: {}", e);
-        String::new()
-    })
-    .trim()
-    .to_string();
+    // ── 2) identify active server (whose files sit on disk) ───────────
+    let active_server = server_state::active_server(&game_folder).unwrap_or_default();
     info!("🔵 [AUTH] Active server: '{}'", active_server);
 
     // hostname of the server
@@ -539,10 +568,17 @@ async fn auth(
     .unwrap_or_default();
     info!("🔵 [AUTH] Server hostname: '{}'", server_name);
 
-    // reuse ButterVersion.txt as an If-None-Match tag
+    // ── read what was last applied from this server ───────────────────
+    let server_state = ServerState::load(&game_folder, &server_name);
+    info!(
+        "🔵 [AUTH] Local version: '{}', ETag: '{}'",
+        server_state.patch_version, server_state.etag
+    );
+
+    // send the applied patch list's ETag as an If-None-Match tag
     let etag_for_header: &str = if active_server == server_name {
-        info!("✅ [AUTH] Server matches active_server, using version as ETag");
-        &local_version
+        info!("✅ [AUTH] Server matches active_server, using stored ETag");
+        &server_state.etag
     } else {
         info!("⚠️ [AUTH] Server changed! Old: '{}', New: '{}'", active_server, server_name);
         ""
@@ -550,17 +586,20 @@ async fn auth(
 
     // ── 3) fetch patch list if patch_server is set ────────────────────
     info!("🔵 [AUTH] Checking for patches...");
+    let patcher_urls = state.state_sync.lock().await.patcher_urls(&auth_resp);
     let mut raw_patcher_resp: Option<PatcherResponse> =
     if !raw_url.is_empty() {
-        info!("🔵 [AUTH] Fetching patch list from: {}", raw_url);
+        info!("🔵 [AUTH] Fetching patch list from: {:?}", patcher_urls);
         let state_sync = state.state_sync.lock().await;
-        let result = server::patcher_request(
+        let result = server::patcher_request_any(
             &state.client,
             state_sync.cancel_shared.clone(),
-                                             &raw_url,
+            state_sync.patcher_config.retry_policy(),
+                                             &patcher_urls,
+                                             server::PATCH_LIST,
                                              etag_for_header,
+                                             state_sync.current_endpoint.patch_public_key.as_deref(),
         )
-        .send()
         .await
         .map_err(|e| {
             error!("❌ [AUTH] Patcher request failed: {}", e);
@@ -576,36 +615,34 @@ async fn auth(
     // ── 3.5) early version-gate: skip only when same server *and* version
     if active_server == server_name && raw_patcher_resp.is_some() {
         info!("🔵 [AUTH] Checking server version...");
-        let version_url = format!("{}/ButterVersion.txt", raw_url);
-        let server_version = match state.client.get(&version_url).send().await {
-            Ok(resp) => {
-                let ver = resp.text().await.unwrap_or_default().trim().to_string();
-                info!("🔵 [AUTH] Server version: '{}'", ver);
-                ver
-            }
-            Err(e) => {
-                warn!("⚠️ [AUTH] Failed to fetch server version: {}", e);
-                String::new()
-            }
+        let (public_key, patcher_config, cancel) = {
+            let state_sync = state.state_sync.lock().await;
+            (
+                state_sync.current_endpoint.patch_public_key.clone(),
+                state_sync.patcher_config.clone(),
+                state_sync.cancel_shared.clone(),
+            )
         };
+        let server_version = patcher::fetch_version(
+            &state.client,
+            &patcher_urls,
+            public_key.as_deref(),
+            &patcher_config,
+            &cancel,
+        )
+        .await;
+        info!("🔵 [AUTH] Server version: {:?}", server_version);
 
-        if server_version == local_version {
+        if server_version.as_ref() == Some(&server_state.patch_version) {
             info!("✅ [AUTH] Versions match, skipping patches");
             raw_patcher_resp = None;
         } else {
-            info!("🔵 [AUTH] Version mismatch! Local: '{}', Server: '{}'", local_version, server_version);
+            info!("🔵 [AUTH] Version mismatch! Local: '{}', Server: {:?}", server_state.patch_version, server_version);
         }
     }
 
-    // ✅ SAVE ACTIVE SERVER TO DISK
-    if !server_name.is_empty() && server_name != active_server {
-        info!("🔵 [AUTH] Updating active_server file to: '{}'", server_name);
-        if let Err(e) = std::fs::write(&active_server_file, &server_name) {
-            error!("❌ [AUTH] Failed to write active_server: {}", e);
-        } else {
-            info!("✅ [AUTH] active_server file updated");
-        }
-    }
+    // The active server only changes once the patcher has put its files on
+    // disk, so it isn't recorded here.
 
     // ── 4) lock and store everything ─────────────────────────────────
     info!("🔵 [AUTH] Storing authentication data...");
@@ -645,6 +682,7 @@ async fn login(
         server::login_request(
             &state.client,
             state_sync.cancel_shared.clone(),
+            state_sync.patcher_config.retry_policy(),
                               &state_sync.current_endpoint,
                               &username,
                               &password,
@@ -670,6 +708,7 @@ async fn register(
         server::register_request(
             &state.client,
             state_sync.cancel_shared.clone(),
+            state_sync.patcher_config.retry_policy(),
                                  &state_sync.current_endpoint,
                                  &username,
                                  &password,
@@ -687,6 +726,7 @@ async fn reauth(state: &mut tauri::State<'_, TauriState>) -> Result<(), String>
         server::login_request(
             &state.client,
             state_sync.cancel_shared.clone(),
+            state_sync.patcher_config.retry_policy(),
                               &state_sync.current_endpoint,
                               &userdata.username,
                               &password,
@@ -709,6 +749,7 @@ async fn get_create_character_request(
     let req = server::create_character_request(
         &state.client,
         state_sync.cancel_shared.clone(),
+        state_sync.patcher_config.retry_policy(),
                                                &state_sync.current_endpoint,
                                                &state_sync.auth_resp_err()?.user.token,
     );
@@ -720,6 +761,8 @@ async fn create_character(
     window: Window,
     mut state: tauri::State<'_, TauriState>,
 ) -> Result<(), String> {
+    let game_folder = state.state_sync.lock().await.effective_folder();
+    folder_lock::check(&game_folder)?;
     let req = get_create_character_request(&mut state).await?;
     let character = match req.send().await {
         Ok(data) => data,
@@ -772,6 +815,7 @@ async fn select_character(
         return Err("game-files-missing: mhf.ini not found".into());
     }
     info!("✅ [SELECT_CHAR] mhf.ini exists");
+    folder_lock::check(&game_folder)?;
 
     let mut state_sync = state.state_sync.lock().await;
     state_sync.exit_reason = Some(ExitSignal::RunGame(character_id, false));
@@ -799,6 +843,7 @@ async fn get_delete_character_request(
     let req = server::delete_character_request(
         &state.client,
         state_sync.cancel_shared.clone(),
+        state_sync.patcher_config.retry_policy(),
                                                &state_sync.current_endpoint,
                                                &state_sync.auth_resp_err()?.user.token,
                                                character_id,
@@ -832,6 +877,7 @@ async fn get_export_character_request(
     let req = server::export_save_request(
         &state.client,
         CancellationToken::new(),
+        state_sync.patcher_config.retry_policy(),
                                           &state_sync.current_endpoint,
                                           &state_sync.auth_resp_err()?.user.token,
                                           character_id,
@@ -871,16 +917,125 @@ async fn export_character(
     path::absolute(path).or(Err("file-error".into()))
 }
 
+#[tauri::command]
+async fn patcher_plan(state: tauri::State<'_, TauriState>) -> Result<patcher::PatchPlanReport, String> {
+    let (patcher_urls, public_key, game_folder, patcher_config) = {
+        let state_sync = state.state_sync.lock().await;
+        (
+            state_sync.patcher_urls(state_sync.auth_resp_err()?),
+         state_sync.current_endpoint.patch_public_key.clone(),
+         state_sync.effective_folder(),
+         state_sync.patcher_config.clone(),
+        )
+    };
+    patcher::plan(
+        &state.client,
+        &patcher_urls,
+        public_key.as_deref(),
+        &game_folder,
+        &patcher_config,
+    )
+        .await
+        .map_err(String::from)
+}
+
+/// Checks the whole install against the server's reference list. When files
+/// need repairing, `patcher_start` then repairs them like a patch.
+#[tauri::command]
+async fn patcher_verify(state: tauri::State<'_, TauriState>) -> Result<patcher::VerifyReport, String> {
+    let (patcher_urls, public_key, game_folder, patcher_config) = {
+        let state_sync = state.state_sync.lock().await;
+        let auth_resp = state_sync.auth_resp.as_ref().ok_or("verify-login-error")?;
+        (
+            state_sync.patcher_urls(auth_resp),
+         state_sync.current_endpoint.patch_public_key.clone(),
+         state_sync.effective_folder(),
+         state_sync.patcher_config.clone(),
+        )
+    };
+    if patcher_urls.is_empty() {
+        return Err("verify-no-server-error".into());
+    }
+    let (report, reference) = patcher::verify(
+        &state.client,
+        &patcher_urls,
+        public_key.as_deref(),
+        &game_folder,
+        &patcher_config,
+    )
+    .await?;
+    if !report.is_clean() {
+        state.state_sync.lock().await.patcher_resp = Some(reference);
+    }
+    Ok(report)
+}
+
+/// Lists what each server's patch changed in the game folder.
+#[tauri::command]
+async fn inspect_manifests(state: tauri::State<'_, TauriState>) -> Result<manifest::Inspection, String> {
+    let game_folder = state.state_sync.lock().await.effective_folder();
+    tauri::async_runtime::spawn_blocking(move || manifest::inspect(&game_folder))
+        .await
+        .or(Err(patcher::FILE_ERROR.into()))
+}
+
+/// Deletes `*.butterold` files no manifest refers to and unused blobs of the
+/// backup store, returning their paths.
+#[tauri::command]
+async fn clean_orphaned_backups(state: tauri::State<'_, TauriState>) -> Result<Vec<String>, String> {
+    let game_folder = state.state_sync.lock().await.effective_folder();
+    tauri::async_runtime::spawn_blocking(move || -> Result<_, &'static str> {
+        let _lock = FolderLock::acquire(&game_folder, "cleanup")?;
+        Ok(manifest::remove_orphaned_backups(&game_folder))
+    })
+    .await
+    .or(Err(patcher::FILE_ERROR))?
+    .map_err(String::from)
+}
+
+/// Takes a snapshot of the whole client to compare and revert against.
+#[tauri::command]
+async fn snapshot_create(state: tauri::State<'_, TauriState>) -> Result<usize, String> {
+    let game_folder = state.state_sync.lock().await.effective_folder();
+    tauri::async_runtime::spawn_blocking(move || snapshot::create(&game_folder))
+        .await
+        .or(Err(patcher::FILE_ERROR))?
+        .map(|snapshot| snapshot.files.len())
+        .map_err(String::from)
+}
+
+/// Lists how the client differs from its snapshot.
+#[tauri::command]
+async fn snapshot_compare(state: tauri::State<'_, TauriState>) -> Result<snapshot::Divergence, String> {
+    let game_folder = state.state_sync.lock().await.effective_folder();
+    tauri::async_runtime::spawn_blocking(move || snapshot::compare(&game_folder))
+        .await
+        .or(Err(patcher::FILE_ERROR))?
+        .map_err(String::from)
+}
+
+/// Reverts the client to its snapshot.
+#[tauri::command]
+async fn snapshot_revert(state: tauri::State<'_, TauriState>) -> Result<snapshot::Divergence, String> {
+    let game_folder = state.state_sync.lock().await.effective_folder();
+    tauri::async_runtime::spawn_blocking(move || snapshot::revert(&game_folder))
+        .await
+        .or(Err(patcher::FILE_ERROR))?
+        .map_err(String::from)
+}
+
 #[tauri::command]
 async fn patcher_start(window: Window, state: tauri::State<'_, TauriState>) -> Result<(), String> {
-    let (patcher_url, patcher_resp, game_folder, cancel) = {
+    let (patcher_urls, patcher_resp, public_key, game_folder, patcher_config, cancel) = {
         let mut state_sync = state.state_sync.lock().await;
         state_sync.cancel_shared.cancel();
         state_sync.cancel_shared = CancellationToken::new();
         (
-            state_sync.auth_resp_err()?.patch_server.clone(),
+            state_sync.patcher_urls(state_sync.auth_resp_err()?),
          state_sync.patcher_resp.take(),
+         state_sync.current_endpoint.patch_public_key.clone(),
          state_sync.effective_folder(),
+         state_sync.patcher_config.clone(),
          state_sync.cancel_shared.clone(),
         )
     };
@@ -891,14 +1046,72 @@ async fn patcher_start(window: Window, state: tauri::State<'_, TauriState>) -> R
     tauri::async_runtime::spawn(patcher::patch(
         window,
         _client,
-        patcher_url,
+        patcher_urls,
         patcher_resp,
+        public_key,
         game_folder,
+        patcher_config,
+        state.rate_limiter.clone(),
         cancel,
     ));
     Ok(())
 }
 
+/// Installs the full client into `folder`, which must be empty, and makes it
+/// the game folder once done. Progress is reported like a patch. Refused for
+/// servers with their own game folder, which would keep using theirs.
+#[tauri::command]
+async fn install_client(
+    window: Window,
+    state: tauri::State<'_, TauriState>,
+    folder: PathBuf,
+) -> Result<(), String> {
+    if !patcher::can_install_into(&folder) {
+        return Err(patcher::INSTALL_FOLDER_ERROR.into());
+    }
+    let (patcher_urls, public_key, patcher_config, cancel) = {
+        let mut state_sync = state.state_sync.lock().await;
+        let auth_resp = state_sync.auth_resp.as_ref().ok_or("install-login-error")?;
+        if state_sync.current_endpoint.game_folder.is_some() {
+            return Err("install-server-folder-error".into());
+        }
+        let patcher_urls = state_sync.patcher_urls(auth_resp);
+        state_sync.cancel_shared.cancel();
+        state_sync.cancel_shared = CancellationToken::new();
+        (
+            patcher_urls,
+            state_sync.current_endpoint.patch_public_key.clone(),
+            state_sync.patcher_config.clone(),
+            state_sync.cancel_shared.clone(),
+        )
+    };
+    if patcher_urls.is_empty() {
+        return Err("install-no-server-error".into());
+    }
+    let client = state.client.clone();
+    let rate_limiter = state.rate_limiter.clone();
+    let state_sync = state.state_sync.clone();
+    tauri::async_runtime::spawn(async move {
+        let installed = patcher::install(
+            window,
+            client,
+            patcher_urls,
+            public_key,
+            folder.clone(),
+            patcher_config,
+            rate_limiter,
+            cancel,
+        )
+        .await;
+        if installed {
+            let mut state_sync = state_sync.lock().await;
+            state_sync.game_folder = Some(folder.clone());
+            state_sync.store.with(|s| s.set("game_folder", Some(folder)));
+        }
+    });
+    Ok(())
+}
+
 #[tauri::command]
 async fn patcher_stop(state: tauri::State<'_, TauriState>) -> Result<(), String> {
     let state_sync = state.state_sync.lock().await;
@@ -929,6 +1142,8 @@ async fn complete_first_run_setup(
             game_folder: None,
             version: mhf_iel::MhfVersion::ZZ,
             is_remote: true,
+            patch_mirrors: Vec::new(),
+            patch_public_key: None,
         };
 
         if !state_sync.remote_endpoints.contains(&avalanche) {
@@ -1037,6 +1252,10 @@ impl From<&server::FriendData> for mhf_iel::FriendData {
     }
 }
 fn main() {
+    if cli::run() {
+        return;
+    }
+
     // ✅ CRITICAL FIX: Forza variabili fontconfig PRIMA di inizializzare Tauri
     #[cfg(target_os = "linux")]
     {
@@ -1112,6 +1331,7 @@ fn main() {
             let mut app = builder
             .manage(TauriState {
                 client: reqwest::ClientBuilder::new().gzip(true).build().unwrap(),
+                    rate_limiter: Arc::new(patcher::RateLimiter::new(0)),
                     state_sync: state_sync.clone(),
             })
             .setup(|app| {
@@ -1159,6 +1379,10 @@ fn main() {
                         store::get(&store, "last_char_id", &mut state_sync.last_char_id);
                         store::get(&store, "serverlist_url", &mut state_sync.serverlist_url);
                         store::get(&store, "messagelist_url", &mut state_sync.messagelist_url);
+                        store::get(&store, "patcher_config", &mut state_sync.patcher_config);
+                        state
+                        .rate_limiter
+                        .set_limit(state_sync.patcher_config.max_bytes_per_second);
                         state_sync
                         .remote_endpoints
                         .apply_config(&state_sync.remote_endpoints_config);
@@ -1171,11 +1395,25 @@ fn main() {
                     }
                 }
                 state_sync.store = StoreHelper::new(store);
+                // A launcher patching the same folder settles its own journal.
+                let folder = state_sync.effective_folder();
+                match FolderLock::acquire(&folder, "recover") {
+                    Ok(_lock) => journal::recover(&folder),
+                    Err(e) => warn!("skipping recovery of the game folder: {}", e),
+                }
                 window.show().unwrap();
+                // Hashing the backups of older launchers can take a while.
+                tauri::async_runtime::spawn_blocking(move || {
+                    match FolderLock::acquire(&folder, "migrate") {
+                        Ok(_lock) => backup_store::migrate_legacy(&folder),
+                        Err(e) => warn!("skipping migration of legacy backups: {}", e),
+                    }
+                });
                 if !state_sync.serverlist_url.is_empty() {
                     let endpoints_req = server::simple_request(
                         &state.client,
                         state_sync.cancel_serverlist.clone(),
+                        state_sync.patcher_config.retry_policy(),
                                                                &state_sync.serverlist_url,
                     );
                     let state_sync_mutex = state.state_sync.clone();
@@ -1188,6 +1426,7 @@ fn main() {
                     let messages_req = server::simple_request(
                         &state.client,
                         state_sync.cancel_messagelist.clone(),
+                        state_sync.patcher_config.retry_policy(),
                                                               &state_sync.messagelist_url,
                     );
                     let state_sync_mutex = state.state_sync.clone();
@@ -1204,6 +1443,7 @@ fn main() {
                 set_style,
                 set_locale,
                 set_setting,
+                set_patcher_config,
                 set_endpoints,
                 set_remote_endpoints,
                 set_current_endpoint,
@@ -1216,8 +1456,16 @@ fn main() {
                 select_character,
                 delete_character,
                 export_character,
+                patcher_plan,
+                patcher_verify,
+                inspect_manifests,
+                clean_orphaned_backups,
+                snapshot_create,
+                snapshot_compare,
+                snapshot_revert,
                 patcher_start,
                 patcher_stop,
+                install_client,
                 patcher::reset_game_files,
                 check_first_run,
                 complete_first_run_setup
@@ -1328,11 +1576,26 @@ fn main() {
             }
         };
         if run {
+            let game_folder = config
+            .mhf_folder
+            .clone()
+            .unwrap_or_else(|| std::env::current_dir().unwrap());
+            // Held while the game runs, so no launcher patches it meanwhile.
+            let lock = match FolderLock::acquire(&game_folder, "launch") {
+                Ok(lock) => lock,
+                Err(e) => {
+                    error!("❌ [GAME_START] Game folder is in use: {}", e);
+                    break;
+                }
+            };
+
             #[cfg(target_os = "windows")]
             {
                 info!("🎮 [GAME_START] Launching Windows game...");
 
-                match mhf_iel::run(config) {  // ← Rimosso .unwrap()
+                let result = mhf_iel::run(config);
+                drop(lock);
+                match result {  // ← Rimosso .unwrap()
                     Ok(exit_code) => {
                         match exit_code {
                             102 => {
@@ -1354,13 +1617,6 @@ fn main() {
 
             #[cfg(target_os = "linux")]
             {
-                use std::path::PathBuf;
-
-                let game_folder: PathBuf = config
-                .mhf_folder
-                .clone()
-                .unwrap_or_else(|| std::env::current_dir().unwrap());
-
                 let cfg_linux = lib_linux::MhfConfigLinux {     game_folder,
                 config,
 
@@ -1369,7 +1625,9 @@ fn main() {
                 info!("🎮 [GAME_START] Launching Linux game via Wine...");
                 // ✅ Lancia il gioco in background detachato dal launcher
                 match lib_linux::run_linux(cfg_linux) {
-                    Ok(_) => {
+                    Ok(pid) => {
+                        // The game outlives the launcher, so it keeps the lock.
+                        lock.hand_over(pid);
                         info!("Game launched successfully");
                         info!("Launcher will close, game continues in background...");
 
@@ -1,37 +1,385 @@
-//! Handles per-server manifest bookkeeping.
-use std::{fs, io, path::{Path, PathBuf}};
-use serde::{Deserialize, Serialize};
-
-pub const MANIFEST_DIR: &str = "ButterClient/Manifests";
-
-#[derive(Default, Serialize, Deserialize)]
-pub struct Manifest {
-    pub modified_files: Vec<String>,
-    pub added_files:    Vec<String>,
-}
-
-impl Manifest {
-    pub fn path(root: &Path, server: &str) -> PathBuf {
-        root.join(MANIFEST_DIR).join(format!("{server}.json"))
-    }
-
-    pub fn load(root: &Path, server: &str) -> Self {
-        fs::read_to_string(Self::path(root, server))
-            .ok()
-            .and_then(|s| serde_json::from_str(&s).ok())
-            .unwrap_or_default()
-    }
-
-    pub fn save(&self, root: &Path, server: &str) -> io::Result<()> {
-        let dir = root.join(MANIFEST_DIR);
-        fs::create_dir_all(&dir)?;
-        let tmp   = dir.join(format!("{server}.json.tmp"));
-        let final_ = dir.join(format!("{server}.json"));
-        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
-        fs::rename(tmp, final_)
-    }
-
-    pub fn delete(root: &Path, server: &str) {
-        let _ = fs::remove_file(Self::path(root, server));
-    }
-}
\ No newline at end of file
+//! Handles per-server manifest bookkeeping.
+//!
+//! Version 1 manifests only listed paths. Version 2 adds an entry per file
+//! with the hashes on either side of the patch, so restoring can tell whether
+//! a backup is still the original and whether the player changed a file
+//! since. Version 1 manifests load as version 2 without entries.
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::backup_store;
+use crate::hash_index::hash_file;
+
+pub const MANIFEST_DIR: &str = "ButterClient/Manifests";
+pub const MANIFEST_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    /// Missing from version 1 manifests.
+    #[serde(default = "legacy_version")]
+    pub version:        u32,
+    pub modified_files: Vec<String>,
+    pub added_files:    Vec<String>,
+    /// Originals removed by a delete directive, kept in the backup store.
+    #[serde(default)]
+    pub deleted_files:  Vec<String>,
+    /// The server's files sit in its overlay cache instead of the game folder.
+    #[serde(default)]
+    pub stashed:        bool,
+    /// Details of the files in the lists above. Files patched before version
+    /// 2 have none.
+    #[serde(default)]
+    pub entries:        BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Hash of the original, which the backup store keeps it under. None if
+    /// the patch added the file.
+    pub original_hash: Option<String>,
+    /// Hash of the file the patch put in place, none if it deleted the file.
+    pub patched_hash:  Option<String>,
+    /// Size of the file the patch put in place.
+    pub size:          u64,
+    /// The server's `ButterVersion.txt` when the patch was applied.
+    pub patch_version: String,
+    /// Unix time the patch was applied.
+    pub applied_at:    u64,
+}
+
+fn legacy_version() -> u32 {
+    1
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            version:        MANIFEST_VERSION,
+            modified_files: Vec::new(),
+            added_files:    Vec::new(),
+            deleted_files:  Vec::new(),
+            stashed:        false,
+            entries:        BTreeMap::new(),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn path(root: &Path, server: &str) -> PathBuf {
+        root.join(MANIFEST_DIR).join(format!("{server}.json"))
+    }
+
+    pub fn load(root: &Path, server: &str) -> Self {
+        let mut manifest: Self = fs::read_to_string(Self::path(root, server))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        if manifest.version < MANIFEST_VERSION {
+            info!("upgrading manifest of {} from version {}", server, manifest.version);
+            manifest.version = MANIFEST_VERSION;
+        }
+        manifest
+    }
+
+    pub fn save(&self, root: &Path, server: &str) -> io::Result<()> {
+        let dir = root.join(MANIFEST_DIR);
+        fs::create_dir_all(&dir)?;
+        let tmp   = dir.join(format!("{server}.json.tmp"));
+        let final_ = dir.join(format!("{server}.json"));
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(tmp, final_)
+    }
+
+    pub fn delete(root: &Path, server: &str) {
+        let _ = fs::remove_file(Self::path(root, server));
+    }
+
+    /// Whether an earlier patch of this server touched `path`.
+    pub fn tracks(&self, path: &str) -> bool {
+        self.modified_files.iter().any(|p| p == path)
+            || self.added_files.iter().any(|p| p == path)
+            || self.deleted_files.iter().any(|p| p == path)
+    }
+
+    /// Hash of the original `path` replaced, which its backup is stored under.
+    pub fn original_hash(&self, path: &str) -> Option<&str> {
+        self.entries.get(path).and_then(|e| e.original_hash.as_deref())
+    }
+
+    /// Records what a patch left at `path`. The original's hash is kept from
+    /// the first patch that touched the file, and dropped with the entry once
+    /// no list tracks the file anymore.
+    pub fn record(
+        &mut self,
+        path: &str,
+        original_hash: Option<String>,
+        patched_hash: Option<String>,
+        size: u64,
+        patch_version: &str,
+    ) {
+        if !self.tracks(path) {
+            self.entries.remove(path);
+            return;
+        }
+        let applied_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let entry = self.entries.entry(path.to_owned()).or_default();
+        if entry.original_hash.is_none() {
+            entry.original_hash = original_hash;
+        }
+        entry.patched_hash = patched_hash;
+        entry.size = size;
+        entry.patch_version = patch_version.to_owned();
+        entry.applied_at = applied_at;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Change {
+    Modified,
+    Added,
+    Deleted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BackupStatus {
+    /// The backup matches the original's recorded hash.
+    Intact,
+    /// The backup exists but no hash was recorded to check it against.
+    Unchecked,
+    /// The backup no longer matches the original's recorded hash.
+    Changed,
+    Missing,
+    /// Added files and stashed servers have no backup in the game folder.
+    None,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InspectedFile {
+    pub path: String,
+    pub change: Change,
+    pub backup: BackupStatus,
+    /// Missing for files patched before manifest version 2.
+    pub entry: Option<ManifestEntry>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerInspection {
+    pub server: String,
+    pub stashed: bool,
+    pub files: Vec<InspectedFile>,
+}
+
+/// What every server's patch changed in a game folder.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Inspection {
+    pub servers: Vec<ServerInspection>,
+    /// `*.butterold` files no manifest accounts for and blobs in the backup
+    /// store nothing refers to, relative to the game folder.
+    pub orphaned_backups: Vec<String>,
+}
+
+/// Names of the servers with a manifest in `root`.
+pub fn servers(root: &Path) -> Vec<String> {
+    let mut servers: Vec<String> = fs::read_dir(root.join(MANIFEST_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            name.strip_suffix(".json").map(str::to_owned)
+        })
+        .collect();
+    servers.sort();
+    servers
+}
+
+/// Loads every manifest in `root` and checks the backups they refer to.
+/// Blocks while hashing backups, so call it off the async runtime.
+pub fn inspect(root: &Path) -> Inspection {
+    let servers = servers(root)
+        .into_iter()
+        .map(|server| {
+            let manifest = Manifest::load(root, &server);
+            let lists = [
+                (Change::Modified, &manifest.modified_files),
+                (Change::Added, &manifest.added_files),
+                (Change::Deleted, &manifest.deleted_files),
+            ];
+            let files = lists
+                .into_iter()
+                .flat_map(|(change, paths)| paths.iter().map(move |path| (change, path)))
+                .map(|(change, path)| {
+                    let entry = manifest.entries.get(path).cloned();
+                    let backup = if change == Change::Added || manifest.stashed {
+                        BackupStatus::None
+                    } else {
+                        backup_status(root, path, entry.as_ref())
+                    };
+                    InspectedFile {
+                        path: path.clone(),
+                        change,
+                        backup,
+                        entry,
+                    }
+                })
+                .collect();
+            ServerInspection {
+                server,
+                stashed: manifest.stashed,
+                files,
+            }
+        })
+        .collect();
+    Inspection {
+        servers,
+        orphaned_backups: orphaned_backups(root),
+    }
+}
+
+fn backup_status(root: &Path, path: &str, entry: Option<&ManifestEntry>) -> BackupStatus {
+    let original_hash = entry.and_then(|e| e.original_hash.as_ref());
+    let Some(bak) = backup_store::locate(root, path, original_hash.map(String::as_str)) else {
+        return BackupStatus::Missing;
+    };
+    match original_hash {
+        None => BackupStatus::Unchecked,
+        Some(hash) if hash_file(&bak).as_ref() == Some(hash) => BackupStatus::Intact,
+        Some(_) => BackupStatus::Changed,
+    }
+}
+
+/// Finds `*.butterold` files that no unstashed manifest backs up, skipping
+/// the launcher's own folders.
+fn orphaned_legacy_backups(root: &Path) -> Vec<String> {
+    let mut referenced = Vec::new();
+    for server in servers(root) {
+        let manifest = Manifest::load(root, &server);
+        if !manifest.stashed {
+            referenced.extend(manifest.modified_files);
+            referenced.extend(manifest.deleted_files);
+        }
+    }
+
+    let mut orphaned = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(root.join(&dir)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let rel = dir.join(&name);
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                if !(dir.as_os_str().is_empty() && (name == "ButterClient" || name == "tmp")) {
+                    dirs.push(rel);
+                }
+                continue;
+            }
+            let Some(original) = name.strip_suffix(".butterold") else {
+                continue;
+            };
+            let original = dir.join(original).to_string_lossy().replace('\\', "/");
+            if !referenced.contains(&original) {
+                orphaned.push(rel.to_string_lossy().replace('\\', "/"));
+            }
+        }
+    }
+    orphaned.sort();
+    orphaned
+}
+
+/// Legacy backups no manifest accounts for and unused blobs of the backup
+/// store, relative to the game folder.
+pub fn orphaned_backups(root: &Path) -> Vec<String> {
+    let mut orphaned = backup_store::unused(root);
+    orphaned.extend(orphaned_legacy_backups(root));
+    orphaned
+}
+
+/// Deletes the orphaned backups in `root`, returning the ones removed.
+pub fn remove_orphaned_backups(root: &Path) -> Vec<String> {
+    let mut removed = backup_store::gc(root);
+    let legacy = orphaned_legacy_backups(root)
+        .into_iter()
+        .filter(|rel| match fs::remove_file(root.join(rel)) {
+            Ok(()) => {
+                info!("removed orphaned backup {}", rel);
+                true
+            }
+            Err(e) => {
+                warn!("failed to remove orphaned backup {}: {}", rel, e);
+                false
+            }
+        });
+    removed.extend(legacy);
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_version_1() {
+        let root = std::env::temp_dir().join("butter-manifest-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(MANIFEST_DIR)).unwrap();
+        fs::write(
+            Manifest::path(&root, "test"),
+            r#"{"modified_files":["a.bin"],"added_files":[]}"#,
+        )
+        .unwrap();
+
+        let manifest = Manifest::load(&root, "test");
+        assert_eq!(manifest.version, MANIFEST_VERSION);
+        assert_eq!(manifest.modified_files, ["a.bin"]);
+        assert!(manifest.entries.is_empty());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_orphaned_backups() {
+        let root = std::env::temp_dir().join("butter-manifest-orphan-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("dat")).unwrap();
+        fs::create_dir_all(root.join("ButterClient")).unwrap();
+        fs::write(root.join("dat/a.bin.butterold"), "original").unwrap();
+        fs::write(root.join("dat/b.bin.butterold"), "original").unwrap();
+        fs::write(root.join("ButterClient/c.bin.butterold"), "launcher data").unwrap();
+        fs::write(root.join("dat/d.bin"), "stored").unwrap();
+        let stored = backup_store::store(&root, &root.join("dat/d.bin")).unwrap();
+        fs::write(root.join("dat/e.bin"), "unused").unwrap();
+        let unused = backup_store::store(&root, &root.join("dat/e.bin")).unwrap();
+        let mut manifest = Manifest {
+            modified_files: vec!["dat/a.bin".into(), "dat/d.bin".into()],
+            ..Default::default()
+        };
+        manifest.entries.entry("dat/d.bin".into()).or_default().original_hash = Some(stored.clone());
+        manifest.save(&root, "test").unwrap();
+
+        let orphaned = [format!("{}/{}", backup_store::BACKUP_DIR, unused), "dat/b.bin.butterold".into()];
+        assert_eq!(orphaned_backups(&root), orphaned);
+        assert_eq!(remove_orphaned_backups(&root), orphaned);
+        assert!(!root.join("dat/b.bin.butterold").exists());
+        assert!(root.join("dat/a.bin.butterold").exists());
+        assert!(!backup_store::blob_path(&root, &unused).exists());
+        assert!(backup_store::blob_path(&root, &stored).exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,222 @@
+//! Per-server overlay cache, so switching between servers doesn't download
+//! the same files again.
+//!
+//! Switching away from a server moves its patched files into
+//! `ButterClient/overlays/<server>/` and puts the originals back. Switching to
+//! a server with a stashed overlay moves its files back into place, after
+//! which the patcher only fetches what changed since. Both steps can be
+//! re-run if interrupted.
+use std::{fs, io, path::{Path, PathBuf}};
+use log::{info, warn};
+
+use crate::backup_store;
+use crate::manifest::Manifest;
+
+pub const OVERLAY_DIR: &str = "ButterClient/overlays";
+
+pub fn path(root: &Path, server: &str) -> PathBuf {
+    root.join(OVERLAY_DIR).join(server)
+}
+
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let _ = fs::remove_file(to);
+    fs::rename(from, to)
+}
+
+/// Moves the files `server` patched into its overlay and restores the
+/// originals they replaced, keeping the manifest for `activate`.
+pub fn stash(root: &Path, server: &str) -> io::Result<()> {
+    // Nothing to stash, e.g. for a server only known from the legacy active
+    // server file. Saving would leave a manifest behind for it.
+    if !Manifest::path(root, server).exists() {
+        return Ok(());
+    }
+    let mut manifest = Manifest::load(root, server);
+    if manifest.stashed {
+        return Ok(());
+    }
+    info!("stashing {} into its overlay", server);
+    let overlay = path(root, server);
+
+    for rel in &manifest.modified_files {
+        let original_hash = manifest.original_hash(rel);
+        if backup_store::locate(root, rel, original_hash).is_some() {
+            let orig = root.join(rel);
+            let cached = overlay.join(rel);
+            // Already cached if an earlier stash was interrupted.
+            if orig.exists() && !cached.exists() {
+                move_file(&orig, &cached)?;
+            }
+            backup_store::restore(root, rel, original_hash)?;
+        }
+    }
+    for rel in &manifest.added_files {
+        let orig = root.join(rel);
+        if orig.exists() {
+            move_file(&orig, &overlay.join(rel))?;
+        }
+    }
+    for rel in &manifest.deleted_files {
+        backup_store::restore(root, rel, manifest.original_hash(rel))?;
+    }
+
+    manifest.stashed = true;
+    manifest.save(root, server)
+}
+
+/// Moves a stashed overlay of `server` back into the game folder. Returns how
+/// many patched files the overlay lost, which the patcher has to fetch again,
+/// or `None` if the server has no stashed overlay.
+pub fn activate(root: &Path, server: &str) -> io::Result<Option<usize>> {
+    let mut manifest = Manifest::load(root, server);
+    if !manifest.stashed {
+        return Ok(None);
+    }
+    info!("activating overlay of {}", server);
+    let overlay = path(root, server);
+
+    // Files missing from the overlay are dropped from the manifest, so the
+    // patcher treats what is on disk as an original and backs it up. Files
+    // without a backup have theirs taken from what is on disk.
+    let mut lost = 0;
+    let mut modified_files = Vec::new();
+    for rel in std::mem::take(&mut manifest.modified_files) {
+        let orig = root.join(rel.as_str());
+        let cached = overlay.join(rel.as_str());
+        let bak = backup_store::locate(root, &rel, manifest.original_hash(&rel));
+        if cached.exists() {
+            if orig.exists() && bak.is_none() {
+                let hash = backup_store::store(root, &orig)?;
+                manifest.entries.entry(rel.clone()).or_default().original_hash = Some(hash);
+            }
+            move_file(&cached, &orig)?;
+        } else {
+            warn!("overlay of {} lost {}", server, rel);
+            lost += 1;
+            // The original stays on disk, tracked while it has a backup.
+            if bak.is_none() {
+                continue;
+            }
+        }
+        modified_files.push(rel);
+    }
+    let mut added_files = Vec::new();
+    for rel in std::mem::take(&mut manifest.added_files) {
+        let cached = overlay.join(rel.as_str());
+        if cached.exists() {
+            move_file(&cached, &root.join(rel.as_str()))?;
+        } else {
+            warn!("overlay of {} lost {}", server, rel);
+            lost += 1;
+            if !root.join(rel.as_str()).exists() {
+                continue;
+            }
+        }
+        added_files.push(rel);
+    }
+    for rel in &manifest.deleted_files {
+        let orig = root.join(rel);
+        if !orig.exists() {
+            continue;
+        }
+        match backup_store::locate(root, rel, manifest.original_hash(rel)) {
+            Some(_) => fs::remove_file(orig)?,
+            None => {
+                let hash = backup_store::store(root, &orig)?;
+                manifest.entries.entry(rel.clone()).or_default().original_hash = Some(hash);
+            }
+        }
+    }
+
+    manifest.modified_files = modified_files;
+    manifest.added_files = added_files;
+    manifest.stashed = false;
+    manifest.save(root, server)?;
+    let _ = fs::remove_dir_all(overlay);
+    Ok(Some(lost))
+}
+
+/// Drops the overlay of `server`, e.g. once its files were restored for good.
+pub fn remove(root: &Path, server: &str) {
+    let _ = fs::remove_dir_all(path(root, server));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stash_and_activate_round_trip() {
+        let root = std::env::temp_dir().join("butter-overlay-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.bin"), "patched").unwrap();
+        fs::write(root.join("a.bin.butterold"), "original").unwrap();
+        fs::write(root.join("b.bin"), "added").unwrap();
+        fs::write(root.join("c.dll.butterold"), "deleted").unwrap();
+        let manifest = Manifest {
+            modified_files: vec!["a.bin".into()],
+            added_files: vec!["b.bin".into()],
+            deleted_files: vec!["c.dll".into()],
+            ..Default::default()
+        };
+        manifest.save(&root, "test").unwrap();
+
+        stash(&root, "test").unwrap();
+        assert_eq!(fs::read_to_string(root.join("a.bin")).unwrap(), "original");
+        assert!(!root.join("b.bin").exists());
+        assert_eq!(fs::read_to_string(root.join("c.dll")).unwrap(), "deleted");
+        assert!(Manifest::load(&root, "test").stashed);
+
+        assert_eq!(activate(&root, "test").unwrap(), Some(0));
+        assert_eq!(fs::read_to_string(root.join("a.bin")).unwrap(), "patched");
+        assert_eq!(fs::read_to_string(root.join("b.bin")).unwrap(), "added");
+        assert!(!root.join("c.dll").exists());
+        let manifest = Manifest::load(&root, "test");
+        assert!(!manifest.stashed);
+        // The originals went into the store, not next to the live files.
+        assert!(!root.join("a.bin.butterold").exists());
+        for (rel, contents) in [("a.bin", "original"), ("c.dll", "deleted")] {
+            let bak = backup_store::locate(&root, rel, manifest.original_hash(rel)).unwrap();
+            assert_eq!(fs::read_to_string(bak).unwrap(), contents);
+        }
+
+        stash(&root, "unpatched").unwrap();
+        assert!(!Manifest::path(&root, "unpatched").exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn test_activate_reports_lost_files() {
+        let root = std::env::temp_dir().join("butter-overlay-lost-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.bin"), "patched").unwrap();
+        fs::write(root.join("a.bin.butterold"), "original").unwrap();
+        fs::write(root.join("b.bin"), "added").unwrap();
+        fs::write(root.join("c.bin"), "added").unwrap();
+        let manifest = Manifest {
+            modified_files: vec!["a.bin".into()],
+            added_files: vec!["b.bin".into(), "c.bin".into()],
+            ..Default::default()
+        };
+        manifest.save(&root, "test").unwrap();
+
+        stash(&root, "test").unwrap();
+        fs::remove_file(path(&root, "test").join("a.bin")).unwrap();
+        fs::remove_file(path(&root, "test").join("b.bin")).unwrap();
+        assert_eq!(activate(&root, "test").unwrap(), Some(2));
+        assert_eq!(fs::read_to_string(root.join("a.bin")).unwrap(), "original");
+        assert!(!root.join("b.bin").exists());
+        assert_eq!(fs::read_to_string(root.join("c.bin")).unwrap(), "added");
+        let manifest = Manifest::load(&root, "test");
+        // Stashing put the original back, so nothing backs it up any more.
+        assert!(manifest.modified_files.is_empty());
+        assert_eq!(manifest.added_files, ["c.bin"]);
+        assert_eq!(activate(&root, "test").unwrap(), None);
+        let _ = fs::remove_dir_all(&root);
+    }
+}
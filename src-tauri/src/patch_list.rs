@@ -0,0 +1,297 @@
+//! Parser for the patch lists served by patch servers.
+//!
+//! Each line is `<sha256>\t<path>`, or `delete\t<path>` for a file that must
+//! be removed. File lines may be followed by more tab-separated `key=value`
+//! fields:
+//! - `delta=<base sha256>:<delta path>` offers a delta from that base version,
+//!   and may be repeated for several bases.
+//! - `encoding=zstd|gzip` serves the full file compressed at `<path>.zst` or
+//!   `<path>.gz`; the hash is still that of the decompressed file.
+//!
+//! Paths are relative to the game folder, a single leading `/` included. A
+//! list that could write outside of it, or that names a file twice, is
+//! rejected as a whole.
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, Write},
+};
+use log::warn;
+
+pub const LIST_ERROR: &str = "patcher-list-error";
+
+/// How a file is stored on the patch server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Identity,
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "zstd" => Some(Self::Zstd),
+            "gzip" => Some(Self::Gzip),
+            _ => None,
+        }
+    }
+
+    /// Suffix appended to the file's path to get the payload's URL.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Identity => "",
+            Self::Zstd => ".zst",
+            Self::Gzip => ".gz",
+        }
+    }
+
+    /// Wraps `file` so that compressed bytes written to it land decompressed.
+    pub fn writer(self, file: fs::File) -> io::Result<Box<dyn Write + Send>> {
+        Ok(match self {
+            Self::Identity => Box::new(file),
+            Self::Zstd => {
+                let mut decoder = zstd::stream::write::Decoder::new(file)?;
+                decoder.window_log_max(crate::delta::WINDOW_LOG_MAX)?;
+                Box::new(decoder)
+            }
+            Self::Gzip => Box::new(flate2::write::GzDecoder::new(file)),
+        })
+    }
+}
+
+/// A delta the server offers for a file, from one specific version of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaSource {
+    pub base_hash: String,
+    /// Location of the delta blob, relative to the patcher URL.
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub path: String,
+    /// Lowercase hex sha256 of the file once decompressed.
+    pub hash: String,
+    pub encoding: Encoding,
+    pub deltas: Vec<DeltaSource>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PatchList {
+    pub files: Vec<FileEntry>,
+    /// Files that must not exist on clients.
+    pub deleted: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    MissingPath,
+    InvalidHash(String),
+    InvalidPath(String),
+    /// The path was already listed on the given line.
+    DuplicatePath(String, usize),
+    InvalidField(String),
+}
+
+/// Why a patch list was rejected, and on which line (starting at 1).
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ErrorKind::MissingPath => write!(f, "missing path"),
+            ErrorKind::InvalidHash(hash) => write!(f, "invalid sha256 '{}'", hash),
+            ErrorKind::InvalidPath(path) => write!(f, "invalid path '{}'", path),
+            ErrorKind::DuplicatePath(path, first) => {
+                write!(f, "'{}' already listed on line {}", path, first)
+            }
+            ErrorKind::InvalidField(field) => write!(f, "invalid field '{}'", field),
+        }
+    }
+}
+
+/// Parses a whole patch list. Blank lines are skipped.
+pub fn parse(content: &str) -> Result<PatchList, ParseError> {
+    let mut list = PatchList::default();
+    // Line each path was first seen on.
+    let mut seen = HashMap::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line_no = i + 1;
+        let error = |kind| ParseError {
+            line: line_no,
+            kind,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let hash = fields.next().unwrap_or_default();
+        let path = fields.next().ok_or_else(|| error(ErrorKind::MissingPath))?;
+        let path = relative_path(path).ok_or_else(|| error(ErrorKind::InvalidPath(path.to_owned())))?;
+        if let Some(first) = seen.insert(path.to_owned(), line_no) {
+            return Err(error(ErrorKind::DuplicatePath(path.to_owned(), first)));
+        }
+        if hash == "delete" {
+            if let Some(field) = fields.next() {
+                return Err(error(ErrorKind::InvalidField(field.to_owned())));
+            }
+            list.deleted.push(path.to_owned());
+            continue;
+        }
+        let hash =
+            parse_hash(hash).ok_or_else(|| error(ErrorKind::InvalidHash(hash.to_owned())))?;
+
+        let mut deltas = Vec::new();
+        let mut encoding = Encoding::Identity;
+        for field in fields {
+            let invalid = || error(ErrorKind::InvalidField(field.to_owned()));
+            match field.split_once('=') {
+                Some(("delta", value)) => {
+                    let (base_hash, delta_path) = value.split_once(':').ok_or_else(invalid)?;
+                    let base_hash = parse_hash(base_hash).ok_or_else(invalid)?;
+                    let delta_path = relative_path(delta_path).ok_or_else(invalid)?;
+                    deltas.push(DeltaSource {
+                        base_hash,
+                        path: delta_path.to_owned(),
+                    });
+                }
+                Some(("encoding", value)) => {
+                    encoding = Encoding::parse(value).ok_or_else(invalid)?;
+                }
+                _ => warn!("ignoring unknown patch list field on line {}: {}", line_no, field),
+            }
+        }
+        list.files.push(FileEntry {
+            path: path.to_owned(),
+            hash,
+            encoding,
+            deltas,
+        });
+    }
+    Ok(list)
+}
+
+/// Normalizes a hex sha256 to lowercase, the way `hash_file` prints it.
+fn parse_hash(hash: &str) -> Option<String> {
+    (hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| hash.to_ascii_lowercase())
+}
+
+/// `path` relative to the game folder, if it stays inside it on every
+/// platform. Older lists start paths with `/` for the game folder's root,
+/// which is dropped; other roots, drives and empty, `.` or `..` components
+/// are refused.
+fn relative_path(path: &str) -> Option<&str> {
+    let path = path.strip_prefix('/').unwrap_or(path);
+    let relative = !path.contains(':')
+        && path
+            .split(['/', '\\'])
+            .all(|c| !c.is_empty() && c != "." && c != "..");
+    relative.then_some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    const HASH_A: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    const HASH_B: &str = "60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752";
+
+    #[test]
+    fn test_parse() {
+        let content = format!(
+            "{}\tdat/mhfdat.bin\tencoding=zstd\tdelta={}:deltas/mhfdat.bin.zst\n\
+             \n\
+             {}\tmhfo-hd.dll\tsomething=new\n\
+             delete\tdat/old.bin\n",
+            HASH_A,
+            HASH_B.to_uppercase(),
+            HASH_B,
+        );
+        let list = parse(&content).unwrap();
+        assert_eq!(list.files.len(), 2);
+        assert_eq!(list.files[0].encoding, Encoding::Zstd);
+        assert_eq!(
+            list.files[0].deltas,
+            vec![DeltaSource {
+                base_hash: HASH_B.to_owned(),
+                path: "deltas/mhfdat.bin.zst".to_owned(),
+            }]
+        );
+        assert_eq!(list.files[1].path, "mhfo-hd.dll");
+        assert_eq!(list.deleted, vec!["dat/old.bin".to_owned()]);
+
+        let list = parse(&format!("{}\t/dat/x\tdelta={}:/deltas/x.zst", HASH_A, HASH_B)).unwrap();
+        assert_eq!(list.files[0].path, "dat/x");
+        assert_eq!(list.files[0].deltas[0].path, "deltas/x.zst");
+        let duplicate = parse(&format!("{}\tdat/x\ndelete\t/dat/x", HASH_A)).unwrap_err();
+        assert_eq!(duplicate.kind, ErrorKind::DuplicatePath("dat/x".to_owned(), 1));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |content: String| parse(&content).unwrap_err();
+        assert_eq!(error(HASH_A.to_owned()).kind, ErrorKind::MissingPath);
+        assert_eq!(
+            error("abc\tmhfo.dll".to_owned()).kind,
+            ErrorKind::InvalidHash("abc".to_owned())
+        );
+        for path in ["//etc/passwd", "/../mhfo.dll", "../../mhfo.dll", "dat/../../x", "C:\\mhfo.dll", "\\\\host\\share", "dat//x"] {
+            assert_eq!(
+                error(format!("{}\t{}", HASH_A, path)).kind,
+                ErrorKind::InvalidPath(path.to_owned())
+            );
+        }
+        let duplicate = error(format!("{}\ta.bin\n{}\tb.bin\ndelete\ta.bin", HASH_A, HASH_B));
+        assert_eq!(duplicate.line, 3);
+        assert_eq!(duplicate.kind, ErrorKind::DuplicatePath("a.bin".to_owned(), 1));
+        assert_eq!(
+            error(format!("{}\ta.bin\tdelta={}:../x", HASH_A, HASH_B)).kind,
+            ErrorKind::InvalidField(format!("delta={}:../x", HASH_B))
+        );
+        assert_eq!(
+            error(format!("{}\ta.bin\tencoding=brotli", HASH_A)).to_string(),
+            "line 1: invalid field 'encoding=brotli'"
+        );
+    }
+
+    #[test]
+    fn test_encoding_writer() {
+        let dir = std::env::temp_dir().join("butter-encoding-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let payload: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let zstd = zstd::encode_all(payload.as_slice(), 3).unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(&payload).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let expected = format!("{:x}", Sha256::digest(&payload));
+
+        for (encoding, body) in [
+            (Encoding::Identity, payload.clone()),
+            (Encoding::Zstd, zstd),
+            (Encoding::Gzip, gzip),
+        ] {
+            let target = dir.join(format!("{:?}", encoding));
+            let mut writer = encoding.writer(fs::File::create(&target).unwrap()).unwrap();
+            // In pieces, the way downloads arrive.
+            for chunk in body.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+            }
+            writer.flush().unwrap();
+            drop(writer);
+            assert_eq!(fs::read(&target).unwrap(), payload, "{:?}", encoding);
+            assert_eq!(crate::hash_index::hash_file(&target).as_deref(), Some(expected.as_str()));
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -1,46 +1,183 @@
 use std::{
+    collections::HashMap,
     fs,
     io::{self, Write},
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 use log::{info, warn};
-use serde::Serialize;
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
 use serde_repr::Serialize_repr;
-use sha2::Digest;
 use tauri::Window;
-use tokio::select;
+use tokio::{select, sync::Semaphore, task::JoinSet};
 use tokio_util::sync::CancellationToken;
-use reqwest;
+use crate::backup_store;
+use crate::folder_lock::FolderLock;
+use crate::hash_index::{hash_file, HashIndex};
+use crate::journal::{self, Journal};
 use crate::manifest::Manifest;
-use crate::{server::PatcherResponse, LogPayload};
+use crate::overlay;
+use crate::patch_list::{self, Encoding};
+use crate::retry::{self, RetryPolicy};
+use crate::server_state::{self, ServerState};
+use crate::server::{self, PatcherResponse};
+use crate::signature::{self, SIGNATURE_ERROR};
+use crate::LogPayload;
 
 pub const NETWORK_ERROR: &str = "patcher-network-error";
 pub const FILE_ERROR: &str = "patcher-file-error";
-const ACTIVE_SERVER_FILE: &str = "ButterClient/active_server";
-/// New: client version file
-const CLIENT_VERSION_FILE: &str = "ButterVersion.txt";
+pub const HASH_MISMATCH_ERROR: &str = "patcher-hash-mismatch";
+pub const DISK_SPACE_ERROR: &str = "patcher-disk-space";
+pub const INSTALL_FOLDER_ERROR: &str = "install-folder-not-empty";
+/// Names the patch a server currently serves, to skip checking every file
+/// when it was already applied.
+const VERSION_FILE: &str = "ButterVersion.txt";
+/// Folders the launcher keeps in a game folder, which may predate a client.
+pub const LAUNCHER_DIRS: &[&str] = &["tmp", "ButterClient", "launcher_config"];
+/// How many times files failing verification are downloaded again.
+const VERIFY_RETRIES: usize = 1;
+/// Assumed ratio of a compressed file's size to its decompressed one when
+/// the payload doesn't record it. Game data can compress well, so this errs
+/// on the high side.
+const COMPRESSION_RATIO_BOUND: u64 = 8;
+/// Largest zstd frame header, which holds the decompressed size.
+const ZSTD_FRAME_HEADER_MAX: u64 = 18;
+
+/// Tunables for the download step, persisted in the launcher store under `patcher_config`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PatcherConfig {
+    pub concurrent_downloads: usize,
+    /// Attempts per request and per interrupted download, including the first.
+    pub retry_attempts: u32,
+    /// Download rate cap across all files, 0 for unlimited.
+    pub max_bytes_per_second: u64,
+}
+
+impl Default for PatcherConfig {
+    fn default() -> Self {
+        Self {
+            concurrent_downloads: 4,
+            retry_attempts: 3,
+            max_bytes_per_second: 0,
+        }
+    }
+}
+
+impl PatcherConfig {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::with_attempts(self.retry_attempts.max(1))
+    }
+}
+
+/// Token bucket shared by every running download. The limit can change at
+/// any time and applies to the next chunk read.
+pub struct RateLimiter {
+    /// Bytes per second, 0 for unlimited.
+    limit: AtomicU64,
+    /// Bytes that may be read right away, and when that was last topped up.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    /// Longest sleep before the limit is checked again.
+    const MAX_SLEEP: Duration = Duration::from_millis(100);
+
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit: AtomicU64::new(limit),
+            bucket: Mutex::new((0.0, Instant::now())),
+        }
+    }
+
+    pub fn set_limit(&self, limit: u64) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Waits until `len` more bytes may be read. Returns `false` if cancelled.
+    async fn acquire(&self, len: usize, cancel: &CancellationToken) -> bool {
+        let mut needed = len as f64;
+        loop {
+            let limit = self.limit.load(Ordering::Relaxed);
+            if limit == 0 {
+                return true;
+            }
+            let limit = limit as f64;
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let (available, last) = &mut *bucket;
+                let now = Instant::now();
+                // At most a second's worth builds up while idle.
+                *available = (*available + now.duration_since(*last).as_secs_f64() * limit).min(limit);
+                *last = now;
+                if *available >= needed {
+                    *available -= needed;
+                    return true;
+                }
+                needed -= *available;
+                *available = 0.0;
+                Duration::from_secs_f64(needed / limit).min(Self::MAX_SLEEP)
+            };
+            select! {
+                _ = cancel.cancelled() => return false,
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    }
+}
+
+/// A delta that rebuilds a file from one specific local version of it.
+#[derive(Debug, Clone)]
+struct Delta {
+    base_hash: String,
+    /// Location of the delta blob, relative to the patcher URL.
+    path: String,
+    /// The local file the delta applies to.
+    base_file: PathBuf,
+}
+
+/// A file from the patch list whose local copy is missing or outdated.
+#[derive(Debug, Clone)]
+struct ChangedPath {
+    path: String,
+    /// Hash of the file once decompressed.
+    hash: String,
+    encoding: Encoding,
+    /// Set when the server offers a delta from the current local file.
+    delta: Option<Delta>,
+}
+
+/// What the patch list asks for, relative to the game folder.
+#[derive(Debug, Default)]
+struct PatchPlan {
+    changed_paths: Vec<ChangedPath>,
+    /// Files the server wants gone that are still on disk.
+    deleted_paths: Vec<String>,
+}
 
 /// foo/bar.txt → foo/bar.txt.butterold
-fn backup_name(p: &Path) -> PathBuf {
+pub fn backup_name(p: &Path) -> PathBuf {
     let mut f = p.file_name().unwrap().to_os_string();
     f.push(".butterold");
     p.with_file_name(f)
 }
 
-/// If `target` exists, rename to `*.butterold`.  
-/// Returns **true** if backed up an existing file (i.e. it counts as “modified”).
-fn backup_original(target: &Path) -> io::Result<bool> {
-    if target.exists() {
-        fs::rename(target, backup_name(target))?;
-        Ok(true)
-    } else {
-        Ok(false)
-    }
+/// tmp/foo/bar.txt → tmp/foo/bar.txt.part
+fn part_name(p: &Path) -> PathBuf {
+    let mut f = p.file_name().unwrap().to_os_string();
+    f.push(".part");
+    p.with_file_name(f)
 }
 
-#[derive(Debug, Serialize_repr, Clone)]
+#[derive(Debug, Default, Serialize_repr, Clone)]
 #[repr(u8)]
 enum State {
+    #[default]
     Checking,
     Downloading,
     Patching,
@@ -48,24 +185,179 @@ enum State {
     Error,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
 struct PatcherEvent {
     total: usize,
     current: usize,
     state: State,
+    /// Estimated size of the whole download, extrapolated for files that
+    /// haven't started yet.
+    total_bytes: u64,
+    downloaded_bytes: u64,
+    current_file: Option<String>,
+    bytes_per_second: u64,
+    eta_seconds: Option<u64>,
+    /// Files being removed because the patch list deletes them.
+    deleted_files: usize,
 }
 
-fn send_event(window: &Window, total: usize, current: usize, state: State) {
+fn emit_event(window: &Window, event: PatcherEvent) {
     window
-        .emit(
-            "patcher",
+        .emit("patcher", event)
+        .unwrap_or_else(|e| warn!("failed to emit message: {}", e));
+}
+
+fn send_event(window: &Window, total: usize, current: usize, state: State) {
+    emit_event(
+        window,
+        PatcherEvent {
+            total,
+            current,
+            state,
+            ..Default::default()
+        },
+    );
+}
+
+/// Minimum time between two byte-level progress events.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Default)]
+struct ProgressCounters {
+    current: usize,
+    files_started: usize,
+    known_bytes: u64,
+    downloaded_bytes: u64,
+    /// Bytes fetched by this run, excluding resumed data, for throughput.
+    session_bytes: u64,
+    current_file: Option<String>,
+    last_emit: Option<Instant>,
+    /// What each file being downloaded adds to the totals, to take it back
+    /// when the file is downloaded again.
+    files: HashMap<String, FileCounters>,
+}
+
+#[derive(Default)]
+struct FileCounters {
+    known_bytes: u64,
+    downloaded_bytes: u64,
+}
+
+/// Download progress shared between the concurrent download tasks.
+struct DownloadProgress {
+    window: Window,
+    total: usize,
+    started: Instant,
+    counters: Mutex<ProgressCounters>,
+}
+
+impl DownloadProgress {
+    fn new(window: &Window, total: usize) -> Self {
+        Self {
+            window: window.clone(),
+            total,
+            started: Instant::now(),
+            counters: Mutex::default(),
+        }
+    }
+
+    /// Registers a file whose response just arrived; `size` is its full
+    /// length when known and `offset` the bytes already on disk.
+    fn start_file(&self, path: &str, size: Option<u64>, offset: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        let known_bytes = size.unwrap_or(offset);
+        counters.files_started += 1;
+        counters.known_bytes += known_bytes;
+        counters.downloaded_bytes += offset;
+        counters.current_file = Some(path.to_owned());
+        let file = FileCounters {
+            known_bytes,
+            downloaded_bytes: offset,
+        };
+        counters.files.insert(path.to_owned(), file);
+    }
+
+    /// Takes back bytes of a file that has to be downloaded again.
+    fn discard_bytes(&self, path: &str, len: u64) {
+        let counters = &mut *self.counters.lock().unwrap();
+        let file = counters.files.entry(path.to_owned()).or_default();
+        let len = len.min(file.downloaded_bytes);
+        file.downloaded_bytes -= len;
+        counters.downloaded_bytes = counters.downloaded_bytes.saturating_sub(len);
+    }
+
+    fn add_bytes(&self, path: &str, len: u64) {
+        let counters = &mut *self.counters.lock().unwrap();
+        let file = counters.files.entry(path.to_owned()).or_default();
+        file.downloaded_bytes += len;
+        // Files of unknown length grow the total as they arrive.
+        if file.downloaded_bytes > file.known_bytes {
+            counters.known_bytes += file.downloaded_bytes - file.known_bytes;
+            file.known_bytes = file.downloaded_bytes;
+        }
+        counters.downloaded_bytes += len;
+        counters.session_bytes += len;
+        if !matches!(counters.last_emit, Some(t) if t.elapsed() < PROGRESS_INTERVAL) {
+            self.emit(counters);
+        }
+    }
+
+    /// Takes back everything counted for a file since `start_file`, before
+    /// it is fetched again from another mirror or as a full file. Resumed
+    /// bytes are counted again when it restarts.
+    fn restart_file(&self, path: &str) {
+        let counters = &mut *self.counters.lock().unwrap();
+        let Some(file) = counters.files.remove(path) else {
+            return;
+        };
+        counters.files_started = counters.files_started.saturating_sub(1);
+        counters.known_bytes = counters.known_bytes.saturating_sub(file.known_bytes);
+        counters.downloaded_bytes = counters.downloaded_bytes.saturating_sub(file.downloaded_bytes);
+    }
+
+    /// Counts a file as done; `size` is given for files that were already
+    /// complete and never went through `start_file`.
+    fn finish_file(&self, path: &str, size: Option<u64>) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.files.remove(path);
+        if let Some(size) = size {
+            counters.files_started += 1;
+            counters.known_bytes += size;
+            counters.downloaded_bytes += size;
+        }
+        counters.current += 1;
+        self.emit(&mut counters);
+    }
+
+    fn emit(&self, counters: &mut ProgressCounters) {
+        counters.last_emit = Some(Instant::now());
+        let unstarted = (self.total - counters.files_started.min(self.total)) as u64;
+        let average = counters.known_bytes / counters.files_started.max(1) as u64;
+        let total_bytes = counters.known_bytes + unstarted * average;
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let bytes_per_second = if elapsed > 0.0 {
+            (counters.session_bytes as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        let eta_seconds = (bytes_per_second > 0)
+            .then(|| total_bytes.saturating_sub(counters.downloaded_bytes) / bytes_per_second);
+        emit_event(
+            &self.window,
             PatcherEvent {
-                total,
-                current,
-                state,
+                total: self.total,
+                current: counters.current,
+                state: State::Downloading,
+                total_bytes,
+                downloaded_bytes: counters.downloaded_bytes,
+                current_file: counters.current_file.clone(),
+                bytes_per_second,
+                eta_seconds,
+                ..Default::default()
             },
-        )
-        .unwrap_or_else(|e| warn!("failed to emit message: {}", e));
+        );
+    }
 }
 
 fn send_error(window: &Window, msg: &str) {
@@ -73,239 +365,1076 @@ fn send_error(window: &Window, msg: &str) {
     window
         .emit("log", LogPayload::error(msg))
         .unwrap_or_else(|e| warn!("failed to emit message: {}", e));
-    window
-        .emit(
-            "patcher",
-            PatcherEvent {
-                total: 0,
-                current: 0,
-                state: State::Error,
-            },
-        )
-        .unwrap_or_else(|e| warn!("failed to emit message: {}", e));
+    send_event(window, 0, 0, State::Error);
 }
 
-fn get_changed_paths<'a>(
-    patcher_content: &'a str,
+/// Compares the patch list against the game folder. Blocks while hashing,
+/// so call it off the async runtime. See `patch_list` for the list format.
+fn get_changed_paths(
+    patcher_content: &str,
     game_folder: &Path,
-) -> Result<Vec<&'a str>, &'static str> {
-    let mut result = Vec::new();
+    index: &mut HashIndex,
+) -> Result<PatchPlan, &'static str> {
+    let list = patch_list::parse(patcher_content).map_err(|e| {
+        warn!("rejected patch list: {}", e);
+        patch_list::LIST_ERROR
+    })?;
+    let deleted_paths = list
+        .deleted
+        .into_iter()
+        .filter(|path| game_folder.join(path).exists())
+        .inspect(|path| info!("delete: {}", path))
+        .collect();
+    let listed: Vec<(ChangedPath, Vec<Delta>)> = list
+        .files
+        .into_iter()
+        .map(|entry| {
+            let deltas = entry
+                .deltas
+                .into_iter()
+                .map(|d| Delta {
+                    base_hash: d.base_hash,
+                    path: d.path,
+                    base_file: game_folder.join(&entry.path),
+                })
+                .collect();
+            let changed_path = ChangedPath {
+                path: entry.path,
+                hash: entry.hash,
+                encoding: entry.encoding,
+                delta: None,
+            };
+            (changed_path, deltas)
+        })
+        .collect();
 
-    for line in patcher_content.lines() {
-        let Some((patcher_hash, mut patcher_path)) = line.split_once('\t') else {
-            return Err(NETWORK_ERROR);
-        };
-        patcher_path = patcher_path.trim_start_matches('/');
-        let client_path = game_folder.join(patcher_path);
-
-        info!(
-            "files: {} {} {}",
-            game_folder.to_str().unwrap(),
-              &patcher_path,
-              &client_path.to_str().unwrap()
-        );
+    let paths: Vec<String> = listed.iter().map(|(c, _)| c.path.clone()).collect();
+    let client_hashes = index.hash_files(game_folder, &paths);
 
-        let mut changed = true;
+    let changed_paths = listed
+        .into_iter()
+        .zip(client_hashes)
+        .filter_map(|((mut changed_path, deltas), client_hash)| {
+            info!(
+                "hashes: {} {} {}",
+                changed_path.path,
+                changed_path.hash,
+                client_hash.as_deref().unwrap_or("missing")
+            );
+            if client_hash.as_deref() == Some(changed_path.hash.as_str()) {
+                return None;
+            }
+            changed_path.delta = client_hash
+                .and_then(|client_hash| deltas.into_iter().find(|d| d.base_hash == client_hash));
+            Some(changed_path)
+        })
+        .collect();
+    Ok(PatchPlan {
+        changed_paths,
+        deleted_paths,
+    })
+}
 
-        if let Ok(mut file) = fs::File::open(&client_path) {
-            let mut hasher = sha2::Sha256::new();
-            if io::copy(&mut file, &mut hasher).is_ok() {
-                let client_hash = format!("{:x}", hasher.finalize());
-                info!("hashes: {} {}", patcher_hash, client_hash);
-                if patcher_hash == client_hash {
-                    changed = false;
+/// Downloads a single file into `target`, resuming from `target.part` when a
+/// previous attempt or run was interrupted. Compressed payloads are
+/// decompressed as they arrive and can't be resumed, so they always start
+/// over. Returns `false` if cancelled.
+#[allow(clippy::too_many_arguments)]
+async fn download_file(
+    client: &reqwest::Client,
+    url: &str,
+    rel_path: &str,
+    target: &Path,
+    encoding: Encoding,
+    retry: &RetryPolicy,
+    limiter: &RateLimiter,
+    progress: &DownloadProgress,
+    cancel: &CancellationToken,
+) -> Result<bool, &'static str> {
+    let part = part_name(target);
+    // Bytes of this file already reported to `progress`.
+    let mut counted = None;
+    let mut range_rejected = false;
+    let mut attempt = 0;
+    'request: loop {
+        if encoding != Encoding::Identity && part.exists() {
+            fs::remove_file(&part).or(Err(FILE_ERROR))?;
+            progress.discard_bytes(rel_path, counted.unwrap_or(0));
+            counted = counted.map(|_| 0);
+        }
+        let offset = fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+        let mut req = client.get(url);
+        if offset > 0 {
+            req = req.header(header::RANGE, format!("bytes={}-", offset));
+        }
+        if offset > 0 || encoding != Encoding::Identity {
+            // Ranges apply to the transfer-encoded body and we decompress
+            // payloads ourselves, so ask for the body as stored.
+            req = req.header(header::ACCEPT_ENCODING, "identity");
+        }
+        let Some(resp) = retry::send(req, retry, cancel).await else {
+            return Ok(false);
+        };
+        let mut resp = resp.map_err(|e| {
+            warn!("patcher request {} failed: {}", url, e);
+            NETWORK_ERROR
+        })?;
+        let append = match resp.status() {
+            StatusCode::PARTIAL_CONTENT => true,
+            // Only retried once, a server that keeps rejecting is broken.
+            StatusCode::RANGE_NOT_SATISFIABLE if !range_rejected => {
+                warn!("server rejected resume of {}, restarting", url);
+                range_rejected = true;
+                fs::remove_file(&part).or(Err(FILE_ERROR))?;
+                progress.discard_bytes(rel_path, counted.unwrap_or(0));
+                counted = counted.map(|_| 0);
+                continue;
+            }
+            status if status.is_success() => false,
+            status => {
+                warn!("patcher request {} failed: {}", url, status);
+                return Err(NETWORK_ERROR);
+            }
+        };
+        let offset = if append {
+            info!("resuming {} at {} bytes", url, offset);
+            offset
+        } else {
+            progress.discard_bytes(rel_path, counted.unwrap_or(0));
+            counted = counted.map(|_| 0);
+            0
+        };
+        if counted.is_none() {
+            progress.start_file(rel_path, resp.content_length().map(|len| len + offset), offset);
+            counted = Some(offset);
+        }
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(&part)
+            .or(Err(FILE_ERROR))?;
+        let mut writer = encoding.writer(file).or(Err(FILE_ERROR))?;
+        loop {
+            let chunk = select! {
+                _ = cancel.cancelled() => return Ok(false),
+                chunk = resp.chunk() => chunk,
+            };
+            match chunk {
+                Ok(Some(chunk)) => {
+                    if !limiter.acquire(chunk.len(), cancel).await {
+                        return Ok(false);
+                    }
+                    writer.write_all(&chunk).map_err(|e| {
+                        warn!("failed to write {}: {}", url, e);
+                        FILE_ERROR
+                    })?;
+                    progress.add_bytes(rel_path, chunk.len() as u64);
+                    counted = counted.map(|c| c + chunk.len() as u64);
+                }
+                Ok(None) => break,
+                Err(e) if retry.can_retry(attempt) => {
+                    warn!("download of {} interrupted, retrying: {}", url, e);
+                    drop(writer);
+                    if !retry.wait(attempt, None, cancel).await {
+                        return Ok(false);
+                    }
+                    attempt += 1;
+                    continue 'request;
+                }
+                Err(e) => {
+                    warn!("download of {} failed: {}", url, e);
+                    return Err(NETWORK_ERROR);
                 }
             }
         }
+        writer.flush().or(Err(FILE_ERROR))?;
+        drop(writer);
+        fs::rename(&part, target).or(Err(FILE_ERROR))?;
+        return Ok(true);
+    }
+}
 
-        if changed {
-            result.push(patcher_path);
-        }
+/// tmp/foo/bar.txt → tmp/foo/bar.txt.zstpatch
+fn delta_name(p: &Path) -> PathBuf {
+    let mut f = p.file_name().unwrap().to_os_string();
+    f.push(".zstpatch");
+    p.with_file_name(f)
+}
+
+/// Builds `target` from a downloaded delta. Returns `false` if cancelled and
+/// an error if the caller should fall back to downloading the full file.
+#[allow(clippy::too_many_arguments)]
+async fn download_delta(
+    client: &reqwest::Client,
+    url: &str,
+    rel_path: &str,
+    delta: &Delta,
+    target: &Path,
+    retry: &RetryPolicy,
+    limiter: &RateLimiter,
+    progress: &DownloadProgress,
+    cancel: &CancellationToken,
+) -> Result<bool, &'static str> {
+    let delta_path = delta_name(target);
+    if !download_file(client, url, rel_path, &delta_path, Encoding::Identity, retry, limiter, progress, cancel).await? {
+        return Ok(false);
     }
+    let base_file = delta.base_file.clone();
+    let base_hash = delta.base_hash.clone();
+    let delta_file = delta_path.clone();
+    let target = target.to_path_buf();
+    let applied = tokio::task::spawn_blocking(move || crate::delta::apply(&base_file, &base_hash, &delta_file, &target))
+        .await
+        .or(Err(FILE_ERROR))?;
+    let _ = fs::remove_file(&delta_path);
+    applied.map_err(|e| {
+        warn!("failed to apply delta {}: {}", url, e);
+        FILE_ERROR
+    })?;
+    Ok(true)
+}
 
-    Ok(result)
+/// Fetches one changed file from `patcher_url`, through its delta when it
+/// has one and falling back to the full file. Returns `false` if cancelled.
+#[allow(clippy::too_many_arguments)]
+async fn download_from(
+    client: &reqwest::Client,
+    patcher_url: &str,
+    changed_path: &ChangedPath,
+    target: &Path,
+    retry: &RetryPolicy,
+    limiter: &RateLimiter,
+    progress: &DownloadProgress,
+    cancel: &CancellationToken,
+) -> Result<bool, &'static str> {
+    let rel_path = &changed_path.path;
+    if let Some(delta) = &changed_path.delta {
+        let url = format!("{}/{}", patcher_url, delta.path);
+        match download_delta(client, &url, rel_path, delta, target, retry, limiter, progress, cancel).await {
+            Err(_) => {
+                warn!("delta for {} unusable, downloading full file", rel_path);
+                progress.restart_file(rel_path);
+            }
+            downloaded => return downloaded,
+        }
+    }
+    let url = format!("{}/{}{}", patcher_url, rel_path, changed_path.encoding.extension());
+    download_file(client, &url, rel_path, target, changed_path.encoding, retry, limiter, progress, cancel).await
 }
 
+/// Downloads `changed_paths` into `patcher_folder` and checks their hashes.
+/// Each file is fetched from `patcher_urls` in order, moving on to the next
+/// mirror when one fails or serves the wrong contents. Every file gets at
+/// least `1 + VERIFY_RETRIES` tries, going through the list again if needed.
+#[allow(clippy::too_many_arguments)]
 async fn download_changed_paths(
     window: &Window,
     client: &reqwest::Client,
-    patcher_url: &str,
-    changed_paths: &[&str],
+    patcher_urls: &[String],
+    changed_paths: &[ChangedPath],
     patcher_folder: &Path,
+    config: &PatcherConfig,
+    limiter: &Arc<RateLimiter>,
     cancel: CancellationToken,
 ) -> Result<(), &'static str> {
-    let total = changed_paths.len();
-    let mut current = 0;
+    let progress = Arc::new(DownloadProgress::new(window, changed_paths.len()));
+    let permits = Arc::new(Semaphore::new(config.concurrent_downloads.max(1)));
+    let retry = config.retry_policy();
+    let attempts = patcher_urls.len().max(1 + VERIFY_RETRIES);
+    // Cancels the remaining downloads as soon as one of them fails.
+    let abort = cancel.child_token();
+    let mut tasks = JoinSet::new();
+
     for changed_path in changed_paths {
-        let req = client
-            .get(format!("{}/{}", patcher_url, changed_path))
-            .send();
-        let mut resp = select! {
-            _ = cancel.cancelled() => return Ok(()),
-            resp = req => resp.or(Err(NETWORK_ERROR))?,
-        };
-        let patcher_path = patcher_folder.join(changed_path);
+        let patcher_path = patcher_folder.join(&changed_path.path);
         fs::create_dir_all(patcher_path.parent().ok_or(FILE_ERROR)?).or(Err(FILE_ERROR))?;
-        let mut file = fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(patcher_path)
-            .or(Err(FILE_ERROR))?;
-        while let Some(chunk) = select! {
-            _ = cancel.cancelled() => return Ok(()),
-            chunk = resp.chunk() => chunk.or(Err(NETWORK_ERROR))?
-        } {
-            file.write_all(&chunk).or(Err(NETWORK_ERROR))?;
+
+        // Left over from an interrupted run that got this file fully.
+        if patcher_path.exists() {
+            let target = patcher_path.clone();
+            let hash = tokio::task::spawn_blocking(move || hash_file(&target))
+                .await
+                .or(Err(FILE_ERROR))?;
+            if hash.as_deref() == Some(&changed_path.hash) {
+                let size = fs::metadata(&patcher_path).map(|m| m.len()).ok();
+                progress.finish_file(&changed_path.path, size);
+                continue;
+            }
         }
-        current += 1;
-        send_event(window, total, current, State::Downloading);
+
+        let client = client.clone();
+        let patcher_urls = patcher_urls.to_vec();
+        let mut changed_path = changed_path.clone();
+        let progress = progress.clone();
+        let limiter = limiter.clone();
+        let permits = permits.clone();
+        let abort = abort.clone();
+        tasks.spawn(async move {
+            let _permit = select! {
+                _ = abort.cancelled() => return Ok(()),
+                permit = permits.acquire_owned() => permit.or(Err(NETWORK_ERROR))?,
+            };
+            let rel_path = changed_path.path.clone();
+            let mut result = Err(NETWORK_ERROR);
+            for patcher_url in patcher_urls.iter().cycle().take(attempts) {
+                let downloaded = download_from(
+                    &client,
+                    patcher_url,
+                    &changed_path,
+                    &patcher_path,
+                    &retry,
+                    &limiter,
+                    &progress,
+                    &abort,
+                )
+                .await;
+                match downloaded {
+                    Ok(false) => return Ok(()),
+                    Ok(true) => {}
+                    Err(e) => {
+                        warn!("failed to download {} from {}: {}", rel_path, patcher_url, e);
+                        progress.restart_file(&rel_path);
+                        result = Err(e);
+                        continue;
+                    }
+                }
+                let target = patcher_path.clone();
+                let hash = tokio::task::spawn_blocking(move || hash_file(&target))
+                    .await
+                    .or(Err(FILE_ERROR))?
+                    .ok_or(FILE_ERROR)?;
+                if hash == changed_path.hash {
+                    info!("{} served by {}", rel_path, patcher_url);
+                    progress.finish_file(&rel_path, None);
+                    return Ok(());
+                }
+                warn!(
+                    "hash mismatch for {} from {}: expected {}, got {}",
+                    rel_path, patcher_url, changed_path.hash, hash
+                );
+                fs::remove_file(&patcher_path).or(Err(FILE_ERROR))?;
+                progress.restart_file(&rel_path);
+                // Fetch the full file next in case the delta was the culprit.
+                changed_path.delta = None;
+                result = Err(HASH_MISMATCH_ERROR);
+            }
+            result
+        });
     }
-    Ok(())
+
+    let mut result = Ok(());
+    while let Some(joined) = tasks.join_next().await {
+        let task_result = joined.unwrap_or(Err(NETWORK_ERROR));
+        if let Err(e) = task_result {
+            if result.is_ok() {
+                result = Err(e);
+                abort.cancel();
+            }
+        }
+    }
+    result
 }
 
-fn move_changed_paths(
-    changed_paths: &[&str],
-    source_folder: &Path,
-    target_folder: &Path,
-    manifest: &mut Manifest,          // ← new
-) -> Result<(), &'static str> {
-    for rel in changed_paths {
-        let source = source_folder.join(rel);
-        let target = target_folder.join(rel);
+/// Download and disk usage of a patch, estimated before anything is fetched.
+#[derive(Debug, Default, Clone, Copy)]
+struct SizeEstimate {
+    download_bytes: u64,
+    /// Space the files take in `<game>/tmp`, which stays in use once they
+    /// are moved into place and the originals into the backup store.
+    required_bytes: u64,
+    /// Files whose size no mirror reported, left out of the totals.
+    unknown_files: usize,
+    known_files: usize,
+}
 
-        fs::create_dir_all(
-            target.parent().ok_or(FILE_ERROR)?
-        ).or(Err(FILE_ERROR))?;
+/// Reads the size of `url` from a HEAD request.
+async fn remote_size(
+    client: &reqwest::Client,
+    url: &str,
+    retry: &RetryPolicy,
+    cancel: &CancellationToken,
+) -> Option<u64> {
+    let req = client.head(url).header(header::ACCEPT_ENCODING, "identity");
+    let resp = retry::send(req, retry, cancel).await?.ok()?;
+    if !resp.status().is_success() {
+        return None;
+    }
+    // `content_length` reports the empty HEAD body, not the header.
+    resp.headers()
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
 
-        match backup_original(&target) {
-            Ok(true)  => manifest.modified_files.push(rel.to_string()),
-            Ok(false) => manifest.added_files.push(rel.to_string()),
-            Err(_)    => return Err(FILE_ERROR),
+/// Reads the decompressed size from the frame header of the zstd payload at
+/// `url`, if the server serves ranges and the header records it. Only the
+/// first frame is read.
+async fn zstd_content_size(
+    client: &reqwest::Client,
+    url: &str,
+    retry: &RetryPolicy,
+    cancel: &CancellationToken,
+) -> Option<u64> {
+    let req = client
+        .get(url)
+        .header(header::ACCEPT_ENCODING, "identity")
+        .header(header::RANGE, format!("bytes=0-{}", ZSTD_FRAME_HEADER_MAX - 1));
+    let resp = retry::send(req, retry, cancel).await?.ok()?;
+    // Anything else would be the whole payload.
+    if resp.status() != StatusCode::PARTIAL_CONTENT {
+        return None;
+    }
+    let header = resp.bytes().await.ok()?;
+    zstd::zstd_safe::get_frame_content_size(&header).ok().flatten()
+}
+
+/// Estimates what fetching `changed_paths` into `patcher_folder` costs,
+/// minus whatever an interrupted run already left there. Compressed files
+/// count at their decompressed size, read from zstd headers or bounded by
+/// `COMPRESSION_RATIO_BOUND`. Files of unknown size count as nothing, and
+/// cancelling leaves the rest unknown.
+async fn estimate_sizes(
+    client: &reqwest::Client,
+    patcher_urls: &[String],
+    changed_paths: &[ChangedPath],
+    patcher_folder: &Path,
+    config: &PatcherConfig,
+    cancel: &CancellationToken,
+) -> SizeEstimate {
+    let retry = config.retry_policy();
+    let permits = Arc::new(Semaphore::new(config.concurrent_downloads.max(1)));
+    let mut tasks = JoinSet::new();
+    for changed_path in changed_paths {
+        let patcher_path = patcher_folder.join(&changed_path.path);
+        if patcher_path.exists() {
+            continue;
         }
+        let (rel_url, base_size) = match &changed_path.delta {
+            Some(delta) => (
+                delta.path.clone(),
+                fs::metadata(&delta.base_file).map(|m| m.len()).ok(),
+            ),
+            None => (
+                format!("{}{}", changed_path.path, changed_path.encoding.extension()),
+                None,
+            ),
+        };
+        let resumed = match (&changed_path.delta, changed_path.encoding) {
+            (None, Encoding::Identity) => fs::metadata(part_name(&patcher_path)).map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        };
+        let encoding = changed_path.encoding;
+        let client = client.clone();
+        let patcher_urls = patcher_urls.to_vec();
+        let permits = permits.clone();
+        let cancel = cancel.clone();
+        tasks.spawn(async move {
+            let _permit = permits.acquire_owned().await.ok()?;
+            for patcher_url in &patcher_urls {
+                let url = format!("{}/{}", patcher_url, rel_url);
+                let Some(size) = remote_size(&client, &url, &retry, &cancel).await else {
+                    if cancel.is_cancelled() {
+                        return None;
+                    }
+                    continue;
+                };
+                let download_bytes = size.saturating_sub(resumed);
+                let required_bytes = match (base_size, encoding) {
+                    // A delta rebuilds a file about as large as the one it
+                    // starts from.
+                    (Some(base_size), _) => download_bytes + base_size,
+                    (None, Encoding::Identity) => download_bytes,
+                    (None, Encoding::Zstd) => match zstd_content_size(&client, &url, &retry, &cancel).await {
+                        Some(content_size) => content_size.max(size),
+                        None => size.saturating_mul(COMPRESSION_RATIO_BOUND),
+                    },
+                    (None, Encoding::Gzip) => size.saturating_mul(COMPRESSION_RATIO_BOUND),
+                };
+                return Some(SizeEstimate {
+                    download_bytes,
+                    required_bytes,
+                    ..Default::default()
+                });
+            }
+            warn!("no mirror reported the size of {}", rel_url);
+            None
+        });
+    }
 
-        fs::rename(&source, &target).or(Err(FILE_ERROR))?;
+    let mut total = SizeEstimate::default();
+    while let Some(joined) = select! {
+        // Dropping the tasks aborts the requests still running.
+        _ = cancel.cancelled() => return total,
+        joined = tasks.join_next() => joined,
+    } {
+        match joined {
+            Ok(Some(estimate)) => {
+                total.download_bytes += estimate.download_bytes;
+                total.required_bytes += estimate.required_bytes;
+                total.known_files += 1;
+            }
+            _ => total.unknown_files += 1,
+        }
     }
-    Ok(())
+    if total.unknown_files > 0 {
+        warn!(
+            "size estimate is incomplete: {} of {} files have an unknown size",
+            total.unknown_files,
+            total.unknown_files + total.known_files
+        );
+    }
+    total
+}
+
+/// Fails with `DISK_SPACE_ERROR` if the game folder's filesystem can't take
+/// `required` more bytes. Unknown free space lets the patch go ahead.
+pub(crate) fn check_disk_space(game_folder: &Path, required: u64) -> Result<(), &'static str> {
+    match fs2::available_space(game_folder) {
+        Ok(available) if available < required => {
+            warn!(
+                "not enough disk space: {} bytes needed, {} available",
+                required, available
+            );
+            Err(DISK_SPACE_ERROR)
+        }
+        Ok(_) => Ok(()),
+        Err(e) => {
+            warn!("unable to read free disk space: {}", e);
+            Ok(())
+        }
+    }
+}
+
+/// What applying the current patch list would change, for diagnosing patcher
+/// problems. Computing it doesn't write anything.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchPlanReport {
+    pub server: String,
+    pub added: Vec<String>,
+    pub replaced: Vec<String>,
+    pub deleted: Vec<String>,
+    pub download_bytes: u64,
+    /// Free space the patch needs on the game folder's filesystem.
+    pub required_bytes: u64,
+    pub available_bytes: Option<u64>,
+}
+
+/// The patch server followed by its mirrors, without duplicates or trailing
+/// slashes. Empty when there is no patch server.
+pub fn mirror_urls<'a>(patch_server: &'a str, mirrors: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    if patch_server.is_empty() {
+        return urls;
+    }
+    for url in std::iter::once(patch_server).chain(mirrors.into_iter().map(String::as_str)) {
+        let url = url.trim().trim_end_matches('/');
+        if !url.is_empty() && !urls.iter().any(|u| u == url) {
+            urls.push(url.to_owned());
+        }
+    }
+    urls
+}
+
+/// Fetches the body of `url`. None if cancelled.
+async fn fetch_text(
+    client: &reqwest::Client,
+    url: &str,
+    retry: &RetryPolicy,
+    cancel: &CancellationToken,
+) -> Option<reqwest::Result<String>> {
+    let resp = match retry::send(client.get(url), retry, cancel).await? {
+        Ok(resp) => resp.error_for_status(),
+        Err(e) => Err(e),
+    };
+    Some(match resp {
+        Ok(resp) => resp.text().await,
+        Err(e) => Err(e),
+    })
+}
+
+/// Reads `ButterVersion.txt` from the first of `patcher_urls` that serves
+/// it, signed by `public_key` if one is pinned like the patch lists. None if
+/// none does or if cancelled, which never matches an applied version.
+pub async fn fetch_version(
+    client: &reqwest::Client,
+    patcher_urls: &[String],
+    public_key: Option<&str>,
+    config: &PatcherConfig,
+    cancel: &CancellationToken,
+) -> Option<String> {
+    let retry = config.retry_policy();
+    for patcher_url in patcher_urls {
+        let url = format!("{}/{}", patcher_url, VERSION_FILE);
+        let version = match fetch_text(client, &url, &retry, cancel).await? {
+            Ok(version) => version,
+            Err(e) => {
+                warn!("failed to fetch {}: {}", url, e);
+                continue;
+            }
+        };
+        if let Some(public_key) = public_key {
+            let signature = match fetch_text(client, &format!("{}.sig", url), &retry, cancel).await? {
+                Ok(signature) => signature,
+                Err(e) => {
+                    warn!("failed to fetch the signature of {}: {}", url, e);
+                    continue;
+                }
+            };
+            if signature::verify(public_key, version.as_bytes(), &signature).is_err() {
+                warn!("{} isn't signed by the pinned key", url);
+                continue;
+            }
+        }
+        return Some(version.trim().to_owned());
+    }
+    None
+}
+
+/// Fetches `list` from `patcher_urls` without an ETag, so it always arrives.
+async fn fetch_list(
+    client: &reqwest::Client,
+    patcher_urls: &[String],
+    list: &str,
+    public_key: Option<&str>,
+    config: &PatcherConfig,
+) -> Result<PatcherResponse, &'static str> {
+    let cancel = CancellationToken::new();
+    server::patcher_request_any(client, cancel, config.retry_policy(), patcher_urls, list, "", public_key)
+        .await
+        .map_err(|e| {
+            warn!("failed to fetch {}: {}", list, e);
+            match e {
+                server::Error::Backend(msg) if msg == SIGNATURE_ERROR => SIGNATURE_ERROR,
+                _ => NETWORK_ERROR,
+            }
+        })?
+        .ok_or(NETWORK_ERROR)
+}
+
+/// Fetches the patch list from `patcher_urls` and reports what patching
+/// `game_folder` with it would do.
+pub async fn plan(
+    client: &reqwest::Client,
+    patcher_urls: &[String],
+    public_key: Option<&str>,
+    game_folder: &Path,
+    config: &PatcherConfig,
+) -> Result<PatchPlanReport, &'static str> {
+    let patcher_resp = fetch_list(client, patcher_urls, server::PATCH_LIST, public_key, config).await?;
+
+    let content = patcher_resp.content.clone();
+    let root = game_folder.to_path_buf();
+    let plan = tokio::task::spawn_blocking(move || {
+        // Cached hashes are used but the index isn't saved.
+        get_changed_paths(&content, &root, &mut HashIndex::load(&root))
+    })
+    .await
+    .or(Err(FILE_ERROR))??;
+
+    let estimate = estimate_sizes(
+        client,
+        patcher_urls,
+        &plan.changed_paths,
+        &game_folder.join("tmp"),
+        config,
+        &CancellationToken::new(),
+    )
+    .await;
+    let (replaced, added) = plan
+        .changed_paths
+        .into_iter()
+        .map(|c| c.path)
+        .partition(|path| game_folder.join(path).exists());
+    Ok(PatchPlanReport {
+        server: patcher_resp.server_name,
+        added,
+        replaced,
+        deleted: plan.deleted_paths,
+        download_bytes: estimate.download_bytes,
+        required_bytes: estimate.required_bytes,
+        available_bytes: fs2::available_space(game_folder).ok(),
+    })
+}
+
+/// Files of a full install that don't match the server's reference list.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyReport {
+    pub server: String,
+    pub missing: Vec<String>,
+    pub corrupt: Vec<String>,
+    /// Files the reference list says must not exist.
+    pub deleted: Vec<String>,
 }
 
-/// Restore every file that was changed by `server`.
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.corrupt.is_empty() && self.deleted.is_empty()
+    }
+}
+
+/// Checks every file of `game_folder` against the server's reference list,
+/// hashing them all again instead of trusting the hash index. Also returns
+/// the reference list, which `patch` repairs the install from.
+pub async fn verify(
+    client: &reqwest::Client,
+    patcher_urls: &[String],
+    public_key: Option<&str>,
+    game_folder: &Path,
+    config: &PatcherConfig,
+) -> Result<(VerifyReport, PatcherResponse), &'static str> {
+    let reference = fetch_list(client, patcher_urls, server::REFERENCE_LIST, public_key, config).await?;
+
+    let content = reference.content.clone();
+    let root = game_folder.to_path_buf();
+    let plan = tokio::task::spawn_blocking(move || {
+        // The fresh hashes replace the index, in case it went stale.
+        let mut index = HashIndex::default();
+        let plan = get_changed_paths(&content, &root, &mut index);
+        if let Err(e) = index.save(&root) {
+            warn!("failed to save hash index: {}", e);
+        }
+        plan
+    })
+    .await
+    .or(Err(FILE_ERROR))??;
+
+    let (corrupt, missing) = plan
+        .changed_paths
+        .into_iter()
+        .map(|c| c.path)
+        .partition(|path| game_folder.join(path).exists());
+    let report = VerifyReport {
+        server: reference.server_name.clone(),
+        missing,
+        corrupt,
+        deleted: plan.deleted_paths,
+    };
+    info!(
+        "verified install: {} missing, {} corrupt, {} to delete",
+        report.missing.len(),
+        report.corrupt.len(),
+        report.deleted.len()
+    );
+    Ok((report, reference))
+}
+
+/// Restore every file that was changed by `server` and forget its overlay.
+///
+/// Refuses to touch anything if a backup no longer matches the original it
+/// was made from, and warns about files changed since they were patched,
+/// whose changes are lost.
 pub fn restore_server(root: &Path, server: &str) -> io::Result<()> {
     let manifest = Manifest::load(root, server);
+    // A stashed server's originals are already in place.
+    if manifest.stashed {
+        Manifest::delete(root, server);
+        ServerState::delete(root, server);
+        overlay::remove(root, server);
+        backup_store::gc(root);
+        return Ok(());
+    }
+    check_restore(root, &manifest)?;
 
-    for rel in manifest.modified_files {
-        let orig = root.join(&rel);
-        let bak  = backup_name(&orig);
-        if bak.exists() {
-            let _ = fs::remove_file(&orig);
-            fs::rename(bak, orig)?;
-        }
+    for rel in manifest.modified_files.iter().chain(&manifest.deleted_files) {
+        backup_store::restore(root, rel, manifest.original_hash(rel))?;
     }
-    for rel in manifest.added_files {
-        let _ = fs::remove_file(root.join(&rel));
+    for rel in &manifest.added_files {
+        let _ = fs::remove_file(root.join(rel));
     }
 
     Manifest::delete(root, server);
+    ServerState::delete(root, server);
+    overlay::remove(root, server);
+    backup_store::gc(root);
     Ok(())
 }
 
+/// Compares backups and patched files against the hashes in `manifest`.
+/// Files patched before manifests had hashes can't be checked.
+fn check_restore(root: &Path, manifest: &Manifest) -> io::Result<()> {
+    for rel in manifest.modified_files.iter().chain(&manifest.deleted_files) {
+        let Some(expected) = manifest.original_hash(rel) else {
+            continue;
+        };
+        let Some(bak) = backup_store::locate(root, rel, Some(expected)) else {
+            continue;
+        };
+        if hash_file(&bak).as_deref() != Some(expected) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("backup of {rel} no longer matches the original"),
+            ));
+        }
+    }
+
+    let patched: Vec<String> = manifest
+        .modified_files
+        .iter()
+        .chain(&manifest.added_files)
+        .filter(|rel| root.join(rel).exists())
+        .cloned()
+        .collect();
+    let hashes = HashIndex::load(root).hash_files(root, &patched);
+    for (rel, hash) in patched.iter().zip(hashes) {
+        let Some(expected) = manifest.entries.get(rel).and_then(|e| e.patched_hash.as_ref()) else {
+            continue;
+        };
+        if hash.as_ref() != Some(expected) {
+            warn!("{} was changed after patching, discarding those changes", rel);
+        }
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn patch_internal(
     window: &Window,
     client: reqwest::Client,
-    patcher_url: String,
+    patcher_urls: &[String],
     patcher_resp: PatcherResponse,
+    patch_version: &str,
     game_folder: &Path,
     patcher_folder: &Path,
+    config: &PatcherConfig,
+    limiter: &Arc<RateLimiter>,
     cancel: CancellationToken,
 ) -> Result<(), &'static str> {
     // ─── 1. compare hashes ───────────────────────────────────────────────
     send_event(window, 0, 0, State::Checking);
-    let changed_paths = get_changed_paths(&patcher_resp.content, game_folder)?;
+    let content = patcher_resp.content.clone();
+    let root = game_folder.to_path_buf();
+    let PatchPlan {
+        changed_paths,
+        deleted_paths,
+    } = tokio::task::spawn_blocking(move || {
+        let mut index = HashIndex::load(&root);
+        let plan = get_changed_paths(&content, &root, &mut index);
+        if let Err(e) = index.save(&root) {
+            warn!("failed to save hash index: {}", e);
+        }
+        plan
+    })
+    .await
+    .or(Err(FILE_ERROR))??;
+
+    // ─── 1b. make sure the patch fits before touching the game folder ────
+    // Reference lists name the whole client, far too many files to ask every
+    // mirror about one by one.
+    if patcher_resp.reference {
+        info!("skipping the disk space check for the reference list");
+    } else {
+        let estimate =
+            estimate_sizes(&client, patcher_urls, &changed_paths, patcher_folder, config, &cancel).await;
+        if estimate.known_files == 0 && estimate.unknown_files > 0 {
+            warn!("no file sizes known, skipping the disk space check");
+        } else {
+            check_disk_space(game_folder, estimate.required_bytes)?;
+        }
+    }
     send_event(window, changed_paths.len(), 0, State::Downloading);
 
-    // ─── 2. download the delta files to <game>/tmp/ ──────────────────────
+    // ─── 2. download and verify the delta files in <game>/tmp/ ───────────
     download_changed_paths(
         window,
         &client,
-        &patcher_url,
+        patcher_urls,
         &changed_paths,
         patcher_folder,
+        config,
+        limiter,
         cancel.clone(),
     )
     .await?;
+    if cancel.is_cancelled() {
+        // keep <game>/tmp so the next run can resume
+        return Ok(());
+    }
 
-    // ─── 3. patch in-place, writing a manifest ───────────────────────────
-    send_event(window, 0, 0, State::Patching);
+    // ─── 3. patch in-place behind a journal, then write the manifest ─────
+    emit_event(
+        window,
+        PatcherEvent {
+            state: State::Patching,
+            deleted_files: deleted_paths.len(),
+            ..Default::default()
+        },
+    );
 
-    let mut manifest = Manifest::default();
-    move_changed_paths(
-        &changed_paths,
-        patcher_folder,
-        game_folder,
-        &mut manifest,
-    )?;
+    let root = game_folder.to_path_buf();
+    let server = patcher_resp.server_name.clone();
+    let patch_version = patch_version.to_owned();
+    let repair = patcher_resp.reference;
+    let files: Vec<_> = changed_paths.iter().map(|c| (c.path.clone(), c.hash.clone())).collect();
+    let mut journal = tokio::task::spawn_blocking(move || {
+        Journal::new(&root, &server, &patch_version, repair, files, deleted_paths)
+    })
+    .await
+    .or(Err(FILE_ERROR))?;
+    if let Err(e) = journal.apply(game_folder, patcher_folder) {
+        warn!("failed to apply patch, rolling back: {}", e);
+        if let Err(e) = journal.rollback(game_folder) {
+            warn!("rollback failed, will retry on next launch: {}", e);
+        }
+        return Err(FILE_ERROR);
+    }
+    journal.commit(game_folder).map_err(|e| {
+        warn!("failed to commit patch, will retry on next launch: {}", e);
+        FILE_ERROR
+    })?;
 
-    manifest
-        .save(game_folder, &patcher_resp.server_name)
-        .unwrap_or_else(|e| warn!("manifest save failed: {}", e));
+    // The applied files were verified above, no need to hash them again.
+    let mut index = HashIndex::load(game_folder);
+    for changed_path in &changed_paths {
+        index.record(game_folder, &changed_path.path, &changed_path.hash);
+    }
+    if let Err(e) = index.save(game_folder) {
+        warn!("failed to save hash index: {}", e);
+    }
 
     // ─── 4. done ─────────────────────────────────────────────────────────
     send_event(window, 0, 0, State::Done);
     Ok(())
 }
 
-/// Main patch entrypoint—replaces the old etag‐based flow.
+/// Whether a fresh client can be installed into `folder`: it doesn't exist
+/// yet or holds nothing but launcher data, e.g. from an interrupted install.
+pub fn can_install_into(folder: &Path) -> bool {
+    match fs::read_dir(folder) {
+        Ok(entries) => entries.flatten().all(|entry| {
+            entry.file_type().is_ok_and(|t| t.is_dir())
+                && LAUNCHER_DIRS.iter().any(|dir| entry.file_name() == *dir)
+        }),
+        Err(e) => e.kind() == io::ErrorKind::NotFound,
+    }
+}
+
+/// Installs the full client from the server's reference list into
+/// `game_folder`, going through the same download, verification and
+/// progress events as a patch. Returns whether the install finished.
+#[allow(clippy::too_many_arguments)]
+pub async fn install(
+    window: Window,
+    client: reqwest::Client,
+    patcher_urls: Vec<String>,
+    public_key: Option<String>,
+    game_folder: PathBuf,
+    config: PatcherConfig,
+    limiter: Arc<RateLimiter>,
+    cancel: CancellationToken,
+) -> bool {
+    send_event(&window, 0, 0, State::Checking);
+    let reference = match fetch_list(&client, &patcher_urls, server::REFERENCE_LIST, public_key.as_deref(), &config).await {
+        Ok(reference) => reference,
+        Err(e) => {
+            send_error(&window, e);
+            return false;
+        }
+    };
+    if let Err(e) = fs::create_dir_all(&game_folder) {
+        warn!("failed to create {:?}: {}", game_folder, e);
+        send_error(&window, FILE_ERROR);
+        return false;
+    }
+    info!("installing client from {} into {:?}", reference.server_name, game_folder);
+
+    let server = reference.server_name.clone();
+    if !patch(window, client, patcher_urls, reference, public_key, game_folder.clone(), config, limiter, cancel).await {
+        return false;
+    }
+    // The fresh client is what resetting returns to, not a patch on top.
+    Manifest::delete(&game_folder, &server);
+    backup_store::gc(&game_folder);
+    true
+}
+
+/// Main patch entrypoint—replaces the old etag‐based flow. Returns whether
+/// the game folder ended up patched.
+#[allow(clippy::too_many_arguments)]
 pub async fn patch(
     window: Window,
     client: reqwest::Client,
-    patcher_url: String,
+    patcher_urls: Vec<String>,
     patcher_resp: PatcherResponse,
+    public_key: Option<String>,
     game_folder: PathBuf,
+    config: PatcherConfig,
+    limiter: Arc<RateLimiter>,
     cancel: CancellationToken,
-) {
-    // ─── Roll back any different server that might still be active ───────────
-    let active_file = game_folder.join(ACTIVE_SERVER_FILE);
-    let prev_server = fs::read_to_string(&active_file)
-        .unwrap_or_default()
-        .trim()
-        .to_string();
+) -> bool {
+    // ─── Keep other launchers out of the game folder until we're done ────────
+    let _lock = match FolderLock::acquire(&game_folder, "patch") {
+        Ok(lock) => lock,
+        Err(e) => {
+            send_error(&window, e);
+            return false;
+        }
+    };
+
+    // ─── Settle a patch that was interrupted while being applied ─────────────
+    journal::recover(&game_folder);
+
+    // ─── Stash any different server that might still be active ───────────────
+    if let Some(prev_server) = server_state::active_server(&game_folder) {
+        if prev_server != patcher_resp.server_name {
+            if let Err(e) = overlay::stash(&game_folder, &prev_server) {
+                warn!("failed to stash {prev_server}: {e}");
+                send_error(&window, FILE_ERROR);
+                return false;
+            }
+            server_state::clear_active_server(&game_folder);
+        }
+    }
 
-    if prev_server != patcher_resp.server_name && !prev_server.is_empty() {
-        if let Err(e) = restore_server(&game_folder, &prev_server) {
-            warn!("failed to restore {prev_server}: {e}");
+    // ─── Bring back this server's overlay, the patch list does the rest ──────
+    match overlay::activate(&game_folder, &patcher_resp.server_name) {
+        Ok(Some(lost)) => {
+            if let Err(e) = server_state::set_active_server(&game_folder, &patcher_resp.server_name) {
+                warn!("failed to write active-server file: {}", e);
+            }
+            // Forget the applied version so the version gate can't skip
+            // fetching what the overlay lost, even if this patch is cancelled.
+            if lost > 0 {
+                let mut server_state = ServerState::load(&game_folder, &patcher_resp.server_name);
+                server_state.patch_version.clear();
+                server_state.etag.clear();
+                if let Err(e) = server_state.save(&game_folder) {
+                    warn!("failed to save server state: {}", e);
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            warn!("failed to activate overlay of {}: {}", patcher_resp.server_name, e);
+            send_error(&window, FILE_ERROR);
+            return false;
         }
     }
 	// 1) version gate
-    let local_version = fs::read_to_string(CLIENT_VERSION_FILE)
-        .unwrap_or_default()
-        .trim()
-        .to_string();
+    let mut server_state = ServerState::load(&game_folder, &patcher_resp.server_name);
 
     // fetch ButterVersion.txt
-    let server_version = match client
-        .get(format!("{}/ButterVersion.txt", &patcher_url))
-        .send()
-        .await
-    {
-        Ok(resp) => resp
-            .text()
-            .await
-            .unwrap_or_default()
-            .trim()
-            .to_string(),
-        Err(_) => "".to_string(),
-    };
+    let server_version =
+        fetch_version(&client, &patcher_urls, public_key.as_deref(), &config, &cancel).await;
 
-    // If versions match, skip patch entirely
-    if server_version == local_version {
+    // If versions match, skip patch entirely. Repairs always run.
+    if !patcher_resp.reference && server_version.as_ref() == Some(&server_state.patch_version) {
         // remember which server is active even though no patch ran
-        if let Some(dir) = active_file.parent() {
-            let _ = fs::create_dir_all(dir);
+        if let Err(e) = server_state::set_active_server(&game_folder, &patcher_resp.server_name) {
+            warn!("failed to write active-server file: {}", e);
+        }
+        server_state.etag = patcher_resp.etag;
+        if let Err(e) = server_state.save(&game_folder) {
+            warn!("failed to save server state: {}", e);
         }
-        let _ = fs::write(&active_file, &patcher_resp.server_name);
 
         send_event(&window, 0, 0, State::Done);
-        return;
+        return true;
     }
 
     // 2) proceed with the normal patch flow
@@ -313,20 +1442,31 @@ pub async fn patch(
     if let Err(e) = fs::create_dir_all(&tmp_folder) {
         warn!("error creating patcher dir: {}", e);
         send_error(&window, FILE_ERROR);
-        return;
+        return false;
     }
 
-    patch_internal(
+    let result = patch_internal(
         &window,
         client,
-        patcher_url,
+        &patcher_urls,
         patcher_resp.clone(),
+        server_version.as_deref().unwrap_or_default(),
         &game_folder,
         &tmp_folder,
-        cancel,
+        &config,
+        &limiter,
+        cancel.clone(),
     )
-    .await
-    .unwrap_or_else(|e| send_error(&window, e));
+    .await;
+
+    // Partial downloads stay in <game>/tmp so the next run resumes them.
+    if let Err(e) = result {
+        send_error(&window, e);
+        return false;
+    }
+    if cancel.is_cancelled() {
+        return false;
+    }
 
     if let Err(e) = fs::remove_dir_all(&tmp_folder) {
         warn!("error deleting patcher dir: {}", e);
@@ -334,43 +1474,42 @@ pub async fn patch(
     }
 	
     // record the server just patched
-    if let Some(dir) = active_file.parent() {
-        let _ = fs::create_dir_all(dir);
-    }
-    if let Err(e) = fs::write(&active_file, &patcher_resp.server_name) {
+    if let Err(e) = server_state::set_active_server(&game_folder, &patcher_resp.server_name) {
         warn!("failed to write active-server file: {}", e);
     }
 
-    // 3) write the new version so next launch is up-to-date
-    if let Err(e) = fs::write(CLIENT_VERSION_FILE, server_version) {
-        warn!("failed to write {}: {}", CLIENT_VERSION_FILE, e);
+    // A repair leaves the patch state alone, the patch list still decides
+    // what the next launch fetches.
+    if patcher_resp.reference {
+        return true;
+    }
+
+    // 3) record the new version so next launch is up-to-date
+    server_state.patch_version = server_version.unwrap_or_default();
+    server_state.etag = patcher_resp.etag;
+    if let Err(e) = server_state.save(&game_folder) {
+        warn!("failed to save server state: {}", e);
     }
+    true
 }
 
 #[tauri::command]
 pub async fn reset_game_files(game_folder: String) -> Result<(), String> {
-    use std::path::PathBuf;
-
-    // same constant
-    const ACTIVE_SERVER_FILE: &str = "ButterClient/active_server";
+    let root = PathBuf::from(&game_folder);
+    let _lock = FolderLock::acquire(&root, "restore")?;
 
-    let root        = PathBuf::from(&game_folder);
-    let active_file = root.join(ACTIVE_SERVER_FILE);
-    let server      = std::fs::read_to_string(&active_file)
-        .unwrap_or_default()
-        .trim()
-        .to_string();
+    journal::recover(&root);
 
     // nothing to do if player never patched
-    if server.is_empty() {
+    let Some(server) = server_state::active_server(&root) else {
         return Ok(());
-    }
+    };
 
     // roll back everything this server touched
     restore_server(&root, &server)
         .map_err(|e| format!("restore failed: {e}"))?;
 
     // remove the “active server” marker so next launch is pristine
-    let _ = std::fs::remove_file(active_file);
+    server_state::clear_active_server(&root);
     Ok(())
 }
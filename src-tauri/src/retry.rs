@@ -0,0 +1,210 @@
+//! Retries for HTTP requests that fail for transient reasons.
+//!
+//! Only idempotent requests are retried, on connection errors and on status
+//! codes that signal a temporary problem. Waits grow exponentially with
+//! jitter, unless the server asks for a specific wait with `Retry-After`.
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+use log::warn;
+use reqwest::{
+    header::{self, HeaderMap},
+    Method, RequestBuilder, Response, StatusCode,
+};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+
+/// Longest `Retry-After` we are willing to wait for.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first one.
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn with_attempts(attempts: u32) -> Self {
+        Self {
+            attempts,
+            ..Default::default()
+        }
+    }
+
+    /// Whether another attempt may follow the `attempt`th one (from 0).
+    pub fn can_retry(&self, attempt: u32) -> bool {
+        attempt + 1 < self.attempts
+    }
+
+    /// How long to wait after the `attempt`th failed attempt (from 0): half
+    /// of the exponential backoff plus a random share of the other half.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(MAX_RETRY_AFTER);
+        }
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay);
+        let half = backoff / 2;
+        // RandomState is randomly seeded, which is all the jitter needs.
+        let random = RandomState::new().build_hasher().finish();
+        half + half.mul_f64(random as f64 / u64::MAX as f64)
+    }
+
+    /// Waits out the delay after a failed attempt. Returns `false` if
+    /// cancelled meanwhile.
+    pub async fn wait(
+        &self,
+        attempt: u32,
+        retry_after: Option<Duration>,
+        cancel: &CancellationToken,
+    ) -> bool {
+        select! {
+            _ = cancel.cancelled() => false,
+            _ = tokio::time::sleep(self.delay(attempt, retry_after)) => true,
+        }
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS | Method::TRACE
+    )
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT
+            | StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Connection problems are worth retrying, malformed requests aren't.
+fn is_transient_error(e: &reqwest::Error) -> bool {
+    !(e.is_builder() || e.is_redirect() || e.is_decode() || e.is_status())
+}
+
+/// The server's `Retry-After` header, in its delay-seconds form.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Sends `request`, retrying it as `policy` allows. Returns the last
+/// response or error, or `None` if cancelled.
+pub async fn send(
+    request: RequestBuilder,
+    policy: &RetryPolicy,
+    cancel: &CancellationToken,
+) -> Option<reqwest::Result<Response>> {
+    let idempotent = request
+        .try_clone()
+        .and_then(|r| r.build().ok())
+        .is_some_and(|r| is_idempotent(r.method()));
+
+    let mut attempt = 0;
+    while idempotent && policy.can_retry(attempt) {
+        // Bodies that can't be cloned can't be sent twice either.
+        let Some(current) = request.try_clone() else {
+            break;
+        };
+        let result = select! {
+            _ = cancel.cancelled() => return None,
+            result = current.send() => result,
+        };
+        let retry_after = match &result {
+            Ok(resp) if is_transient_status(resp.status()) => {
+                warn!("request to {} failed with {}, retrying", resp.url(), resp.status());
+                retry_after(resp.headers())
+            }
+            Err(e) if is_transient_error(e) => {
+                warn!("request failed, retrying: {}", e);
+                None
+            }
+            _ => return Some(result),
+        };
+        if !policy.wait(attempt, retry_after, cancel).await {
+            return None;
+        }
+        attempt += 1;
+    }
+    select! {
+        _ = cancel.cancelled() => None,
+        result = request.send() => Some(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_bounds() {
+        let policy = RetryPolicy::default();
+        for attempt in 0..20 {
+            let backoff = policy
+                .base_delay
+                .saturating_mul(1 << attempt.min(16))
+                .min(policy.max_delay);
+            let delay = policy.delay(attempt, None);
+            assert!(delay >= backoff / 2 && delay <= backoff, "attempt {}: {:?}", attempt, delay);
+        }
+        assert!(policy.delay(30, None) <= policy.max_delay);
+        assert!(policy.can_retry(1) && !policy.can_retry(2));
+        assert!(!RetryPolicy::with_attempts(1).can_retry(0));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(0, Some(Duration::from_secs(5))), Duration::from_secs(5));
+        assert_eq!(policy.delay(0, Some(Duration::from_secs(3600))), MAX_RETRY_AFTER);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(header::RETRY_AFTER, " 7 ".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(7)));
+        // The HTTP-date form isn't supported and falls back to backoff.
+        headers.insert(header::RETRY_AFTER, "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap());
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn test_filters() {
+        assert!(is_idempotent(&Method::GET) && is_idempotent(&Method::PUT));
+        assert!(!is_idempotent(&Method::POST) && !is_idempotent(&Method::PATCH));
+        for status in [429, 500, 502, 503, 504, 408] {
+            assert!(is_transient_status(StatusCode::from_u16(status).unwrap()), "{}", status);
+        }
+        for status in [200, 304, 400, 401, 404, 501] {
+            assert!(!is_transient_status(StatusCode::from_u16(status).unwrap()), "{}", status);
+        }
+    }
+}
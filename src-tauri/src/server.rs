@@ -6,12 +6,17 @@ use reqwest::{RequestBuilder, Response};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 use serde_repr::{Deserialize_repr, Serialize_repr};
-use tokio::select;
 use tokio_util::sync::CancellationToken;
 
+use crate::retry::{self, RetryPolicy};
+use crate::signature::{self, SIGNATURE_ERROR};
 use crate::{endpoint::Endpoint, patcher};
 
 const NETWORK_ERROR: &str = "launcher-network-error";
+/// Files that changed since the client's patch.
+pub const PATCH_LIST: &str = "check";
+/// Every file of a full install, used to verify and repair it.
+pub const REFERENCE_LIST: &str = "full";
 
 pub enum Error {
     Cancellation,
@@ -152,6 +157,9 @@ pub struct AuthResponse {
 	#[serde(default, deserialize_with = "null_default")]
 	pub friends: Vec<FriendData>,
     pub patch_server: String,
+    /// Mirrors of `patch_server`, tried in order when it fails.
+    #[serde(default, deserialize_with = "null_default")]
+    pub patch_mirrors: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -162,6 +170,10 @@ pub struct PatcherResponse {
     pub etag: String,
     pub content: String,
     pub server_name: String,     // ← NEW
+    /// Whether `content` is the reference list of a full install rather
+    /// than a patch.
+    #[serde(default)]
+    pub reference: bool,
 }
 
 #[derive(Serialize)]
@@ -177,11 +189,14 @@ struct TokenRequest<'a> {
     token: &'a str,
 }
 
-async fn send(request: RequestBuilder, cancel: CancellationToken) -> Result<Response, Error> {
-    let resp = select! {
-        _ = cancel.cancelled() => return Err(Error::Cancellation),
-        resp = request.send() => resp,
-    };
+async fn send(
+    request: RequestBuilder,
+    retry: &RetryPolicy,
+    cancel: CancellationToken,
+) -> Result<Response, Error> {
+    let resp = retry::send(request, retry, &cancel)
+        .await
+        .ok_or(Error::Cancellation)?;
     let resp = resp.map_err(|e| {
         warn!("request connection failed: {}", e);
         Error::Backend(NETWORK_ERROR.into())
@@ -207,17 +222,18 @@ async fn send(request: RequestBuilder, cancel: CancellationToken) -> Result<Resp
 
 pub struct JsonRequest<T: DeserializeOwned> {
     request: RequestBuilder,
+    retry: RetryPolicy,
     cancel: CancellationToken,
     _phantom: PhantomData<T>,
 }
 
 impl<T: DeserializeOwned> JsonRequest<T> {
-    fn new(request: RequestBuilder, cancel: CancellationToken) -> Self {
-        Self { request, cancel, _phantom: PhantomData }
+    fn new(request: RequestBuilder, retry: RetryPolicy, cancel: CancellationToken) -> Self {
+        Self { request, retry, cancel, _phantom: PhantomData }
     }
 
     pub async fn send(self) -> Result<T, Error> {
-        let resp = send(self.request, self.cancel).await?;
+        let resp = send(self.request, &self.retry, self.cancel).await?;
         // ─── diagnostic dump ───────────────────────────────────────
         let text = resp.text().await.map_err(|e| {
             warn!("failed to read body: {}", e);
@@ -234,12 +250,13 @@ impl<T: DeserializeOwned> JsonRequest<T> {
 
 pub struct PatcherRequest {
     request: RequestBuilder,
+    retry: RetryPolicy,
     cancel: CancellationToken,
 }
 
 impl PatcherRequest {
     pub async fn send(self) -> Result<Option<PatcherResponse>, Error> {
-        let resp   = send(self.request, self.cancel).await?;
+        let resp   = send(self.request, &self.retry, self.cancel).await?;
         let status = resp.status().as_u16();
 
         if status == 304 {
@@ -260,43 +277,110 @@ impl PatcherRequest {
             Error::Server(status, patcher::NETWORK_ERROR.into())   // ← no resp here
         })?;
 
-        Ok(Some(PatcherResponse { etag, content, server_name }))
+        Ok(Some(PatcherResponse { etag, content, server_name, reference: false }))
     }
 }
 
+/// Checks the `list` that `url` served against the signature next to it.
+async fn verify_patcher_response(
+    client: &reqwest::Client,
+    cancel: CancellationToken,
+    retry: RetryPolicy,
+    url: &str,
+    list: &str,
+    resp: &PatcherResponse,
+    public_key: &str,
+) -> Result<(), Error> {
+    let sig = send(client.get(format!("{}/{}.sig", url, list)), &retry, cancel)
+        .await
+        .map_err(|e| match e {
+            Error::Cancellation => e,
+            _ => Error::Backend(SIGNATURE_ERROR.into()),
+        })?
+        .text()
+        .await
+        .map_err(|_| Error::Backend(SIGNATURE_ERROR.into()))?;
+    signature::verify(public_key, resp.content.as_bytes(), &sig)
+        .map_err(|e| Error::Backend(e.into()))
+}
+
+/// Fetches `list` from the first of `urls` that serves it, signed by
+/// `public_key` if one is pinned. The response is named after the first
+/// URL, so mirrors share its state.
+pub async fn patcher_request_any(
+    client: &reqwest::Client,
+    cancel: CancellationToken,
+    retry: RetryPolicy,
+    urls: &[String],
+    list: &str,
+    client_etag: &str,
+    public_key: Option<&str>,
+) -> Result<Option<PatcherResponse>, Error> {
+    let mut result = Err(Error::Backend(NETWORK_ERROR.into()));
+    for url in urls {
+        result = patcher_request(client, cancel.clone(), retry, url, list, client_etag)
+            .send()
+            .await;
+        if let (Ok(Some(resp)), Some(public_key)) = (&result, public_key) {
+            if let Err(e) = verify_patcher_response(client, cancel.clone(), retry, url, list, resp, public_key).await {
+                result = Err(e);
+            }
+        }
+        match &result {
+            Ok(_) | Err(Error::Cancellation) => break,
+            Err(e) => warn!("patch list from {} unavailable: {}", url, e),
+        }
+    }
+    let server_name = urls
+        .first()
+        .and_then(|url| reqwest::Url::parse(url).ok())
+        .and_then(|url| url.host_str().map(str::to_owned));
+    result.map(|resp| {
+        resp.map(|resp| PatcherResponse {
+            server_name: server_name.unwrap_or(resp.server_name.clone()),
+            reference: list == REFERENCE_LIST,
+            ..resp
+        })
+    })
+}
+
 pub fn simple_request<T: DeserializeOwned>(
     client: &reqwest::Client,
     cancel: CancellationToken,
+    retry: RetryPolicy,
     url: &str,
 ) -> JsonRequest<T> {
     let req = client.get(url);
-    JsonRequest::new(req, cancel)
+    JsonRequest::new(req, retry, cancel)
 }
 
 pub fn launcher_request(
     client: &reqwest::Client,
     cancel: CancellationToken,
+    retry: RetryPolicy,
     endpoint: &Endpoint,
 ) -> JsonRequest<LauncherResponse> {
     let req = client.get(endpoint.get_url("/launcher"));
-    JsonRequest::new(req, cancel)
+    JsonRequest::new(req, retry, cancel)
 }
 
 pub fn login_request(
     client: &reqwest::Client,
     cancel: CancellationToken,
+    retry: RetryPolicy,
     endpoint: &Endpoint,
     username: &str,
     password: &str,
 ) -> JsonRequest<AuthResponse> {
     let auth_req = AuthRequest { username, password };
     let req = client.post(endpoint.get_url("/login")).json(&auth_req);
-    JsonRequest::new(req, cancel)
+    JsonRequest::new(req, retry, cancel)
 }
 
 pub fn register_request(
     client: &reqwest::Client,
     cancel: CancellationToken,
+    retry: RetryPolicy,
     endpoint: &Endpoint,
     username: &str,
     password: &str,
@@ -305,12 +389,13 @@ pub fn register_request(
     let req = client
         .post(endpoint.get_url("/register"))
         .json(&auth_req);
-    JsonRequest::new(req, cancel)
+    JsonRequest::new(req, retry, cancel)
 }
 
 pub fn delete_character_request(
     client: &reqwest::Client,
     cancel: CancellationToken,
+    retry: RetryPolicy,
     endpoint: &Endpoint,
     token: &str,
     character_id: i32,
@@ -322,12 +407,13 @@ pub fn delete_character_request(
     let req = client
         .post(endpoint.get_url("/character/delete"))
         .json(&delete_request);
-    JsonRequest::new(req, cancel)
+    JsonRequest::new(req, retry, cancel)
 }
 
 pub fn create_character_request(
     client: &reqwest::Client,
     cancel: CancellationToken,
+    retry: RetryPolicy,
     endpoint: &Endpoint,
     token: &str,
 ) -> JsonRequest<CharacterData> {
@@ -335,12 +421,13 @@ pub fn create_character_request(
     let req = client
         .post(endpoint.get_url("/character/create"))
         .json(&token_req);
-    JsonRequest::new(req, cancel)
+    JsonRequest::new(req, retry, cancel)
 }
 
 pub fn export_save_request(
     client: &reqwest::Client,
     cancel: CancellationToken,
+    retry: RetryPolicy,
     endpoint: &Endpoint,
     token: &str,
     character_id: i32,
@@ -352,20 +439,22 @@ pub fn export_save_request(
     let req = client
         .post(endpoint.get_url("/character/export"))
         .json(&export_request);
-    JsonRequest::new(req, cancel)
+    JsonRequest::new(req, retry, cancel)
 }
 
 pub fn patcher_request(
     client: &reqwest::Client,
     cancel: CancellationToken,
+    retry: RetryPolicy,
     url: &str,
+    list: &str,
     client_etag: &str,
 ) -> PatcherRequest {
-    let mut request = client.get(format!("{}/check", url));
+    let mut request = client.get(format!("{}/{}", url, list));
 
     if !client_etag.is_empty() {
         request = request.header("If-None-Match", client_etag);
     }
 
-    PatcherRequest { request, cancel }
+    PatcherRequest { request, retry, cancel }
 }
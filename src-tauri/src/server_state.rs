@@ -0,0 +1,116 @@
+//! Installed server state: which server's files are on disk, and which patch
+//! each server last applied to the game folder.
+use std::{fs, io, path::{Path, PathBuf}};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+pub const SERVER_STATE_DIR: &str = "ButterClient/ServerState";
+pub const ACTIVE_SERVER_FILE: &str = "ButterClient/active_server";
+/// Older launchers also tracked the active server here, from the auth step.
+const LEGACY_ACTIVE_SERVER_FILES: &[&str] = &["launcher_config/active_server"];
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ServerState {
+    pub server: String,
+    /// Contents of the server's `ButterVersion.txt` when it was last applied.
+    pub patch_version: String,
+    /// ETag of the patch list that was applied, sent back as `If-None-Match`.
+    pub etag: String,
+}
+
+impl ServerState {
+    pub fn path(root: &Path, server: &str) -> PathBuf {
+        root.join(SERVER_STATE_DIR).join(format!("{server}.json"))
+    }
+
+    pub fn load(root: &Path, server: &str) -> Self {
+        fs::read_to_string(Self::path(root, server))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_else(|| Self {
+                server: server.to_owned(),
+                ..Default::default()
+            })
+    }
+
+    pub fn save(&self, root: &Path) -> io::Result<()> {
+        let dir = root.join(SERVER_STATE_DIR);
+        fs::create_dir_all(&dir)?;
+        let tmp = dir.join(format!("{}.json.tmp", self.server));
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(tmp, Self::path(root, &self.server))
+    }
+
+    pub fn delete(root: &Path, server: &str) {
+        let _ = fs::remove_file(Self::path(root, server));
+    }
+}
+
+/// The server whose patch is currently applied to the game folder, if any.
+pub fn active_server(root: &Path) -> Option<String> {
+    migrate_legacy(root);
+    let server = fs::read_to_string(root.join(ACTIVE_SERVER_FILE)).ok()?;
+    let server = server.trim();
+    (!server.is_empty()).then(|| server.to_owned())
+}
+
+pub fn set_active_server(root: &Path, server: &str) -> io::Result<()> {
+    let file = root.join(ACTIVE_SERVER_FILE);
+    fs::create_dir_all(file.parent().unwrap())?;
+    fs::write(file, server)
+}
+
+pub fn clear_active_server(root: &Path) {
+    let _ = fs::remove_file(root.join(ACTIVE_SERVER_FILE));
+}
+
+/// Folds legacy active-server files into `ACTIVE_SERVER_FILE`. The patcher's
+/// file wins when both exist, since only it was written after patching.
+fn migrate_legacy(root: &Path) {
+    let file = root.join(ACTIVE_SERVER_FILE);
+    for legacy in LEGACY_ACTIVE_SERVER_FILES {
+        let legacy = root.join(legacy);
+        if !legacy.exists() {
+            continue;
+        }
+        if !file.exists() {
+            let server = fs::read_to_string(&legacy).unwrap_or_default();
+            info!("migrating active server '{}' from {:?}", server.trim(), legacy);
+            if let Err(e) = set_active_server(root, server.trim()) {
+                warn!("failed to migrate {:?}: {}", legacy, e);
+                continue;
+            }
+        }
+        let _ = fs::remove_file(&legacy);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_legacy() {
+        let root = std::env::temp_dir().join("butter-server-state-test");
+        let _ = fs::remove_dir_all(&root);
+        let legacy = root.join(LEGACY_ACTIVE_SERVER_FILES[0]);
+        fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        fs::write(&legacy, "Rain\n").unwrap();
+
+        assert_eq!(active_server(&root).as_deref(), Some("Rain"));
+        assert_eq!(fs::read_to_string(root.join(ACTIVE_SERVER_FILE)).unwrap(), "Rain");
+        assert!(!legacy.exists());
+        // No patch version was recorded, so the next start patches again.
+        let state = ServerState::load(&root, "Rain");
+        assert_eq!(state.server, "Rain");
+        assert!(state.patch_version.is_empty());
+        assert!(state.etag.is_empty());
+
+        // The patcher's file wins over a leftover legacy one.
+        fs::write(&legacy, "Other").unwrap();
+        assert_eq!(active_server(&root).as_deref(), Some("Rain"));
+        assert!(!legacy.exists());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,52 @@
+//! Ed25519 signatures over patch lists.
+//!
+//! Servers that pin a public key in their `Endpoint` publish `<list>.sig`
+//! next to each list they serve, such as `check.sig` next to `check`: the
+//! base64 signature of the list's exact bytes. `ButterVersion.txt.sig` signs
+//! the patch version the same way, since a matching version skips patching.
+//! A list without a valid signature is never applied for such servers, so
+//! a hijacked patch server or mirror can't push files to players.
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signature, VerifyingKey};
+use log::warn;
+
+pub const SIGNATURE_ERROR: &str = "patcher-signature-error";
+
+/// Parses a base64 public key, as pinned in an `Endpoint`.
+pub fn parse_key(public_key: &str) -> Option<VerifyingKey> {
+    let bytes = STANDARD.decode(public_key.trim()).ok()?;
+    VerifyingKey::from_bytes(&bytes.try_into().ok()?).ok()
+}
+
+/// Checks the base64 `signature` of `content` against `public_key`.
+pub fn verify(public_key: &str, content: &[u8], signature: &str) -> Result<(), &'static str> {
+    let key = parse_key(public_key).ok_or(SIGNATURE_ERROR)?;
+    let signature = STANDARD
+        .decode(signature.trim())
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or(SIGNATURE_ERROR)?;
+    key.verify_strict(content, &signature).map_err(|e| {
+        warn!("patch list signature rejected: {}", e);
+        SIGNATURE_ERROR
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_verify() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = STANDARD.encode(signing_key.verifying_key().as_bytes());
+        let content = b"0123abcd\tmhfdat.bin\n";
+        let signature = STANDARD.encode(signing_key.sign(content).to_bytes());
+
+        assert!(verify(&public_key, content, &signature).is_ok());
+        assert!(verify(&public_key, b"0123abcd\tmhfo.dll\n", &signature).is_err());
+        assert!(verify(&public_key, content, "").is_err());
+        assert!(parse_key("not a key").is_none());
+    }
+}
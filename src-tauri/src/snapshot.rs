@@ -0,0 +1,286 @@
+//! Baseline of the whole client, to tell what changed since and put it back.
+//!
+//! The snapshot lists the hash and size of every file in the game folder
+//! outside the launcher's own folders, and keeps a copy of each in the backup
+//! store so any file can be reverted, not just the ones patches touched.
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::backup_store;
+use crate::folder_lock::FolderLock;
+use crate::hash_index::HashIndex;
+use crate::journal;
+use crate::manifest::{self, Manifest};
+use crate::overlay::OVERLAY_DIR;
+use crate::patcher::{check_disk_space, FILE_ERROR, LAUNCHER_DIRS};
+use crate::server_state::{self, SERVER_STATE_DIR};
+
+pub const SNAPSHOT_FILE: &str = "ButterClient/snapshot.json";
+pub const SNAPSHOT_MISSING_ERROR: &str = "snapshot-missing-error";
+pub const SNAPSHOT_PATCHED_ERROR: &str = "snapshot-patched-error";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub hash: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snapshot {
+    /// Unix time the snapshot was taken.
+    pub created_at: u64,
+    /// Keyed by path relative to the game folder, with `/` separators.
+    pub files: BTreeMap<String, SnapshotFile>,
+}
+
+impl Snapshot {
+    pub fn path(root: &Path) -> PathBuf {
+        root.join(SNAPSHOT_FILE)
+    }
+
+    pub fn load(root: &Path) -> Option<Self> {
+        fs::read_to_string(Self::path(root))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+    }
+
+    pub fn save(&self, root: &Path) -> io::Result<()> {
+        let final_ = Self::path(root);
+        fs::create_dir_all(final_.parent().unwrap())?;
+        let tmp = final_.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(self)?)?;
+        fs::rename(tmp, final_)
+    }
+}
+
+/// How the game folder differs from the snapshot. Paths are relative to the
+/// game folder.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Divergence {
+    pub created_at: u64,
+    pub modified: Vec<String>,
+    pub missing: Vec<String>,
+    /// Files the snapshot doesn't list, which reverting deletes.
+    pub added: Vec<String>,
+    /// Modified or missing files whose copy is gone from the backup store.
+    pub unrecoverable: Vec<String>,
+}
+
+/// Every client file in `root`, skipping the launcher's own folders.
+fn client_files(root: &Path) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(root.join(&dir)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let rel = dir.join(entry.file_name());
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                if !(dir.as_os_str().is_empty()
+                    && LAUNCHER_DIRS.iter().any(|d| entry.file_name() == *d))
+                {
+                    dirs.push(rel);
+                }
+                continue;
+            }
+            files.push(rel.to_string_lossy().replace('\\', "/"));
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Hashes the files in `root`, saving the updated hash index.
+fn hash_client(root: &Path) -> BTreeMap<String, String> {
+    let files = client_files(root);
+    let mut index = HashIndex::load(root);
+    let hashes = index.hash_files(root, &files);
+    if let Err(e) = index.save(root) {
+        warn!("failed to save hash index: {}", e);
+    }
+    files
+        .into_iter()
+        .zip(hashes)
+        .filter_map(|(rel, hash)| Some((rel, hash?)))
+        .collect()
+}
+
+/// Takes a snapshot of the client in `root` as it is now, replacing any
+/// earlier one. Fails with `SNAPSHOT_PATCHED_ERROR` while a server's patch is
+/// applied, since its files would become the baseline and reverting would
+/// then let the originals go. Blocks while hashing and copying the whole
+/// client, so call it off the async runtime.
+pub fn create(root: &Path) -> Result<Snapshot, &'static str> {
+    let _lock = FolderLock::acquire(root, "snapshot")?;
+    journal::recover(root);
+    if let Some(server) = manifest::servers(root)
+        .into_iter()
+        .find(|server| !Manifest::load(root, server).stashed)
+    {
+        warn!("refusing to take a snapshot while {} is patched", server);
+        return Err(SNAPSHOT_PATCHED_ERROR);
+    }
+
+    let mut files = BTreeMap::new();
+    for (rel, hash) in hash_client(root) {
+        let size = fs::metadata(root.join(&rel)).map(|m| m.len()).unwrap_or(0);
+        files.insert(rel, SnapshotFile { hash, size });
+    }
+    // Files already in the store aren't copied again.
+    let required = files
+        .values()
+        .filter(|file| !backup_store::blob_path(root, &file.hash).exists())
+        .map(|file| file.size)
+        .sum();
+    check_disk_space(root, required)?;
+    // The copies are hashed again, the hash index only tells what to expect.
+    for (rel, file) in files.iter_mut() {
+        match backup_store::store_copy(root, &root.join(rel)) {
+            Ok(hash) => file.hash = hash,
+            Err(e) => {
+                warn!("failed to copy {} into the backup store: {}", rel, e);
+                return Err(FILE_ERROR);
+            }
+        }
+    }
+    let snapshot = Snapshot {
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        files,
+    };
+    if let Err(e) = snapshot.save(root) {
+        warn!("failed to save snapshot: {}", e);
+        return Err(FILE_ERROR);
+    }
+    // Copies of an earlier snapshot may no longer be needed.
+    backup_store::gc(root);
+    info!("snapshot taken of {} files", snapshot.files.len());
+    Ok(snapshot)
+}
+
+/// Compares `root` against its snapshot. Blocks while hashing, so call it
+/// off the async runtime.
+pub fn compare(root: &Path) -> Result<Divergence, &'static str> {
+    let snapshot = Snapshot::load(root).ok_or(SNAPSHOT_MISSING_ERROR)?;
+    Ok(diverge(root, &snapshot))
+}
+
+fn diverge(root: &Path, snapshot: &Snapshot) -> Divergence {
+    let mut current = hash_client(root);
+    let mut divergence = Divergence {
+        created_at: snapshot.created_at,
+        ..Default::default()
+    };
+    for (rel, file) in &snapshot.files {
+        let list = match current.remove(rel) {
+            Some(hash) if hash == file.hash => continue,
+            Some(_) => &mut divergence.modified,
+            None => &mut divergence.missing,
+        };
+        if !backup_store::blob_path(root, &file.hash).exists() {
+            divergence.unrecoverable.push(rel.clone());
+        }
+        list.push(rel.clone());
+    }
+    divergence.added = current.into_keys().collect();
+    divergence
+}
+
+/// Puts every file in `root` back the way the snapshot recorded it and
+/// deletes the files it doesn't list. Servers' patches are undone along the
+/// way, so their manifests, states and overlays are dropped too. Returns what
+/// diverged before reverting.
+pub fn revert(root: &Path) -> Result<Divergence, &'static str> {
+    let _lock = FolderLock::acquire(root, "snapshot")?;
+    journal::recover(root);
+
+    let snapshot = Snapshot::load(root).ok_or(SNAPSHOT_MISSING_ERROR)?;
+    let divergence = diverge(root, &snapshot);
+    for rel in divergence.modified.iter().chain(&divergence.missing) {
+        if divergence.unrecoverable.contains(rel) {
+            warn!("no copy of {} left to revert it", rel);
+            continue;
+        }
+        let blob = backup_store::blob_path(root, &snapshot.files[rel].hash);
+        let path = root.join(rel);
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::copy(blob, &path));
+        if let Err(e) = result {
+            warn!("failed to revert {}: {}", rel, e);
+            return Err(FILE_ERROR);
+        }
+    }
+    for rel in &divergence.added {
+        if let Err(e) = fs::remove_file(root.join(rel)) {
+            warn!("failed to remove {}: {}", rel, e);
+            return Err(FILE_ERROR);
+        }
+    }
+
+    for server in manifest::servers(root) {
+        Manifest::delete(root, &server);
+    }
+    let _ = fs::remove_dir_all(root.join(SERVER_STATE_DIR));
+    let _ = fs::remove_dir_all(root.join(OVERLAY_DIR));
+    server_state::clear_active_server(root);
+    backup_store::gc(root);
+    info!(
+        "reverted {} files and removed {} added ones",
+        divergence.modified.len() + divergence.missing.len() - divergence.unrecoverable.len(),
+        divergence.added.len()
+    );
+    Ok(divergence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_and_revert() {
+        let root = std::env::temp_dir().join("butter-snapshot-test");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("dat")).unwrap();
+        fs::write(root.join("mhf.exe"), "game").unwrap();
+        fs::write(root.join("dat/a.bin"), "original").unwrap();
+        fs::write(root.join("dat/b.bin"), "original").unwrap();
+        assert_eq!(compare(&root).err(), Some(SNAPSHOT_MISSING_ERROR));
+
+        create(&root).unwrap();
+        fs::write(root.join("dat/a.bin"), "patched").unwrap();
+        fs::remove_file(root.join("dat/b.bin")).unwrap();
+        fs::write(root.join("dat/c.bin"), "added").unwrap();
+
+        let divergence = compare(&root).unwrap();
+        assert_eq!(divergence.modified, ["dat/a.bin"]);
+        assert_eq!(divergence.missing, ["dat/b.bin"]);
+        assert_eq!(divergence.added, ["dat/c.bin"]);
+        assert!(divergence.unrecoverable.is_empty());
+
+        revert(&root).unwrap();
+        assert_eq!(fs::read_to_string(root.join("dat/a.bin")).unwrap(), "original");
+        assert_eq!(fs::read_to_string(root.join("dat/b.bin")).unwrap(), "original");
+        assert!(!root.join("dat/c.bin").exists());
+        let divergence = compare(&root).unwrap();
+        assert!(divergence.modified.is_empty() && divergence.missing.is_empty());
+
+        // Revert forgot every patch, so a patched server has to be set up
+        // again to check the refusal.
+        Manifest::default().save(&root, "test").unwrap();
+        assert_eq!(create(&root).err(), Some(SNAPSHOT_PATCHED_ERROR));
+        let _ = fs::remove_dir_all(&root);
+    }
+}
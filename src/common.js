@@ -11,6 +11,9 @@ export const SETTINGS_PAGE = 3;
 export const DELETE_DIALOG = 0;
 export const SERVERS_DIALOG = 1;
 export const PATCHER_DIALOG = 2;
+export const REPAIR_DIALOG = 3;
+export const INSPECT_DIALOG = 4;
+export const SNAPSHOT_DIALOG = 5;
 
 export const CHECKING_PATCHER = 0;
 export const DOWNLOADING_PATCHER = 1;
@@ -45,6 +48,21 @@ export function formatDate(ts) {
   return d.toISOString().slice(0, 10);
 }
 
+export function formatBytes(bytes) {
+  const units = ["B", "KB", "MB", "GB"];
+  let i = 0;
+  while (bytes >= 1024 && i < units.length - 1) {
+    bytes /= 1024;
+    i++;
+  }
+  return `${bytes.toFixed(i === 0 ? 0 : 1)} ${units[i]}`;
+}
+
+export function formatDuration(seconds) {
+  const minutes = Math.floor(seconds / 60);
+  return minutes > 0 ? `${minutes}m ${seconds % 60}s` : `${seconds}s`;
+}
+
 export function openPicker(picker) {
   if (picker.value) return;
   picker.value = true;
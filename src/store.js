@@ -12,12 +12,17 @@ import {
   SETTINGS_PAGE,
   PATCHER_PAGE,
   PATCHER_DIALOG,
+  REPAIR_DIALOG,
+  INSPECT_DIALOG,
+  SNAPSHOT_DIALOG,
   CHECKING_PATCHER,
   DONE_PATCHER,
   ERROR_PATCHER,
   DOWNLOADING_PATCHER,
   PATCHING_PATCHER,
   GAME_VERSIONS,
+  formatBytes,
+  formatDuration,
 } from "./common";
 
 import fallbackCog            from '/classic/cog.png';
@@ -54,11 +59,21 @@ const storePrivate = reactive({
 
   editEndpointNew: false,
   deleteCharacter: null,
+  verifyReport: null,
+  inspection: null,
+  divergence: null,
+  installFolder: null,
 
   patcher: {
     total: 0,
     current: 0,
     state: DONE_PATCHER,
+    totalBytes: 0,
+    downloadedBytes: 0,
+    currentFile: null,
+    bytesPerSecond: 0,
+    etaSeconds: null,
+    deletedFiles: 0,
   },
 
   settings: {
@@ -88,6 +103,7 @@ export const storeMut = reactive({
   editEndpoint: null,
   serverlistUrl: "",
   messagelistUrl: "",
+  patcherConfig: null,
 });
 
 export function logText(level, text) {
@@ -167,11 +183,37 @@ export const patcherPercentage = computed(() => {
     case CHECKING_PATCHER:
       return 0;
     case DOWNLOADING_PATCHER:
+      if (storePrivate.patcher.totalBytes) {
+        return (
+          storePrivate.patcher.downloadedBytes / storePrivate.patcher.totalBytes
+        );
+      }
       return storePrivate.patcher.current / (storePrivate.patcher.total || 1);
     default:
       return 1;
   }
 });
+export const patcherDetail = computed(() => {
+  const patcher = storePrivate.patcher;
+  if (patcher.state === PATCHING_PATCHER && patcher.deletedFiles) {
+    return getMessage("patcher-deleting", { count: patcher.deletedFiles });
+  }
+  if (patcher.state !== DOWNLOADING_PATCHER || !patcher.totalBytes) {
+    return null;
+  }
+  const args = {
+    downloaded: formatBytes(patcher.downloadedBytes),
+    total: formatBytes(patcher.totalBytes),
+    speed: formatBytes(patcher.bytesPerSecond),
+  };
+  if (patcher.etaSeconds === null) {
+    return getMessage("patcher-bytes", args);
+  }
+  return getMessage("patcher-bytes-eta", {
+    ...args,
+    eta: formatDuration(patcher.etaSeconds),
+  });
+});
 export const patcherLog = computed(() => {
   switch (storePrivate.patcher.state) {
     case CHECKING_PATCHER:
@@ -233,6 +275,14 @@ watch(
   async (messagelistUrl) =>
     await handleInvoke("set_messagelist_url", { messagelistUrl })
 );
+watch(
+  () => storeMut.patcherConfig,
+  async (patcherConfig) => {
+    if (patcherConfig === null) return;
+    await handleInvoke("set_patcher_config", { patcherConfig });
+  },
+  { deep: true }
+);
 
 export const backgroundUrl      = computed(() => storePrivate.background     ?? fallbackBackground);
 export const cogUrl             = computed(() => storePrivate.cog            ?? fallbackCog);
@@ -314,6 +364,7 @@ export async function initStore() {
   storeMut.gameFolder    = data.gameFolder;
   storeMut.serverlistUrl = data.serverlistUrl;
   storeMut.messagelistUrl= data.messagelistUrl;
+  storeMut.patcherConfig = data.patcherConfig;
 
   storePrivate.endpoints        = data.endpoints;
   storePrivate.remoteEndpoints  = data.remoteEndpoints;
@@ -369,6 +420,9 @@ const dialogCallbackMap = {
   [DELETE_DIALOG]: dialogDeleteCharacterConfirm,
   [SERVERS_DIALOG]: dialogSaveEndpoint,
   [PATCHER_DIALOG]: dialogStartPatcher,
+  [REPAIR_DIALOG]: dialogStartPatcher,
+  [INSPECT_DIALOG]: dialogCleanBackups,
+  [SNAPSHOT_DIALOG]: dialogRevertSnapshot,
 };
 export function dialogCallback() {
   dialogCallbackMap[storePrivate.dialogKind]();
@@ -387,6 +441,7 @@ export function dialogAddEndpoint() {
     gamePort: null,
     gamePath: null,
     version: GAME_VERSIONS[0],
+    patchPublicKey: null,
   };
   storePrivate.editEndpointNew = true;
   storePrivate.dialogKind = SERVERS_DIALOG;
@@ -446,11 +501,69 @@ export async function dialogStartPatcher() {
     storeMut.page = PATCHER_PAGE;
   });
 }
+export async function verifyGameFiles() {
+  const report = await handleInvoke("patcher_verify");
+  if (
+    report.missing.length + report.corrupt.length + report.deleted.length ===
+    0
+  ) {
+    logMessage("info", "verify-clean");
+    return;
+  }
+  storePrivate.verifyReport = report;
+  storePrivate.dialogKind = REPAIR_DIALOG;
+  storePrivate.dialogOpen = true;
+}
+export async function inspectManifests() {
+  storePrivate.inspection = await handleInvoke("inspect_manifests");
+  storePrivate.dialogKind = INSPECT_DIALOG;
+  storePrivate.dialogOpen = true;
+}
+export async function dialogCleanBackups() {
+  await hanldeDialogClose(async () => {
+    if (storePrivate.inspection.orphanedBackups.length === 0) return;
+    const removed = await handleInvoke("clean_orphaned_backups");
+    logMessage("info", "inspect-cleaned", { count: removed.length });
+  });
+}
+export async function createSnapshot() {
+  const count = await handleInvoke("snapshot_create");
+  logMessage("info", "snapshot-created", { count });
+}
+export async function compareSnapshot() {
+  storePrivate.divergence = await handleInvoke("snapshot_compare");
+  storePrivate.dialogKind = SNAPSHOT_DIALOG;
+  storePrivate.dialogOpen = true;
+}
+export async function dialogRevertSnapshot() {
+  await hanldeDialogClose(async () => {
+    const divergence = storePrivate.divergence;
+    if (
+      divergence.modified.length +
+        divergence.missing.length +
+        divergence.added.length ===
+      0
+    )
+      return;
+    await handleInvoke("snapshot_revert");
+    logMessage("info", "snapshot-reverted");
+  });
+}
+export async function installClient(folder) {
+  await handleInvoke("install_client", { folder });
+  storePrivate.installFolder = folder;
+  storeMut.page = PATCHER_PAGE;
+}
 export async function completePatcher() {
+  if (storePrivate.installFolder !== null) {
+    storeMut.gameFolder = storePrivate.installFolder;
+    storePrivate.installFolder = null;
+  }
   storePrivate.authLoading = false;
   storeMut.page = CHARACTERS_PAGE;
 }
 export async function cancelPatcher() {
+  storePrivate.installFolder = null;
   await handleInvoke("patcher_stop");
   storePrivate.authLoading = false;
   storeMut.page = LOGIN_PAGE;
@@ -466,6 +579,7 @@ export async function setEndpoints(endpoints, remote) {
     ...endpoint,
     launcherPort: endpoint.launcherPort || null,
     gamePort: endpoint.gamePort || null,
+    patchPublicKey: endpoint.patchPublicKey || null,
   }));
   let currentEndpoint;
   if (remote) {